@@ -0,0 +1,9 @@
+-- `execute_after` used to be a delay (in seconds), which the delivery worker
+-- slept for while holding the row lock. it is now the point in time after
+-- which a failed delivery may be attempted again, so that the worker can move
+-- on to other recipients in the meantime
+UPDATE issue_delivery_queue SET n_retries = 0 WHERE n_retries IS NULL;
+ALTER TABLE issue_delivery_queue ALTER COLUMN n_retries SET NOT NULL;
+ALTER TABLE issue_delivery_queue DROP COLUMN execute_after;
+ALTER TABLE issue_delivery_queue
+   ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
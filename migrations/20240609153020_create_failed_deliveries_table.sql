@@ -0,0 +1,12 @@
+-- dead letters: deliveries that are given up after too many retries are moved
+-- here (instead of being rolled back into `issue_delivery_queue`, where they
+-- would be retried forever), to be retried or discarded by an admin
+CREATE TABLE failed_deliveries(
+   newsletter_issue_id uuid NOT NULL
+      REFERENCES newsletter_issues (newsletter_issue_id),
+   subscriber_email TEXT NOT NULL,
+   n_attempts SMALLINT NOT NULL,
+   last_error TEXT NOT NULL,
+   failed_at timestamptz NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,8 @@
+-- `content` now holds the Markdown source of an issue; the rendered bodies
+-- are stored alongside it at publish time. existing issues were written
+-- before Markdown was supported, so their content is used as-is
+ALTER TABLE newsletter_issues ADD COLUMN html_content TEXT;
+ALTER TABLE newsletter_issues ADD COLUMN text_content TEXT;
+UPDATE newsletter_issues SET html_content = content, text_content = content;
+ALTER TABLE newsletter_issues ALTER COLUMN html_content SET NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN text_content SET NOT NULL;
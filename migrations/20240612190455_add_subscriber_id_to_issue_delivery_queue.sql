@@ -0,0 +1,11 @@
+-- issues are rendered per recipient, which requires the whole subscriber row,
+-- not just the email. tasks whose subscriber has since been removed would
+-- have been skipped anyway
+DELETE FROM issue_delivery_queue
+WHERE subscriber_email NOT IN (SELECT email FROM subscriptions);
+ALTER TABLE issue_delivery_queue
+   ADD COLUMN subscriber_id uuid REFERENCES subscriptions (id);
+UPDATE issue_delivery_queue q SET subscriber_id = s.id
+FROM subscriptions s
+WHERE s.email = q.subscriber_email;
+ALTER TABLE issue_delivery_queue ALTER COLUMN subscriber_id SET NOT NULL;
@@ -0,0 +1,12 @@
+-- issues can now be scheduled for later, in which case they are only
+-- published (and enqueued for delivery) once `send_at` has passed.
+-- `published_at` was always set to now() (as text) on insert; it is now a
+-- proper timestamp, left empty until the issue is actually published
+ALTER TABLE newsletter_issues
+   ALTER COLUMN published_at TYPE timestamptz USING published_at::timestamptz;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+
+-- 'scheduled', 'published' or 'cancelled'
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE newsletter_issues ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE newsletter_issues ADD COLUMN send_at timestamptz;
@@ -0,0 +1,16 @@
+-- delivery log: one row per finished delivery, written in the same transaction
+-- that removes the task from `issue_delivery_queue`. together, the two tables
+-- give the progress of an issue
+CREATE TABLE issue_deliveries(
+   newsletter_issue_id uuid NOT NULL
+      REFERENCES newsletter_issues (newsletter_issue_id),
+   -- not a foreign key, since subscribers may be removed
+   subscriber_id uuid NOT NULL,
+   subscriber_email TEXT NOT NULL,
+   -- 'sent', 'skipped' (subscriber removed, or invalid email) or 'failed'
+   -- (dead-lettered, see `failed_deliveries`)
+   status TEXT NOT NULL,
+   n_attempts SMALLINT NOT NULL,
+   finished_at timestamptz NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,14 @@
+-- the delivery log becomes the record of every email sent: the id assigned by
+-- the provider (if any, see `EmailProvider::send_email`), and how long the
+-- delivery took from being enqueued
+ALTER TABLE issue_delivery_queue
+   ADD COLUMN enqueued_at timestamptz NOT NULL DEFAULT now();
+
+ALTER TABLE issue_deliveries ADD COLUMN provider_message_id TEXT;
+ALTER TABLE issue_deliveries ADD COLUMN enqueued_at timestamptz;
+UPDATE issue_deliveries SET enqueued_at = finished_at;
+ALTER TABLE issue_deliveries ALTER COLUMN enqueued_at SET NOT NULL;
+
+-- "did alice get issue X?"
+CREATE INDEX issue_deliveries_subscriber_email_idx
+   ON issue_deliveries (subscriber_email);
@@ -0,0 +1,6 @@
+-- confirmation links expire (see `ApplicationSettings::confirmation_token_ttl_hours`),
+-- and can only be used once. existing tokens are treated as freshly issued
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+-- set by `confirm`; consumed tokens are kept (for a while) so that clicking
+-- the link again is harmless
+ALTER TABLE subscription_tokens ADD COLUMN consumed_at timestamptz NULL;
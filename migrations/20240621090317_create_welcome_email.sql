@@ -0,0 +1,23 @@
+-- the welcome email, sent (once) to every subscriber when they confirm. it is
+-- rendered for the subscriber on confirmation, and sent through the same
+-- outbox as the confirmation email; it is not a newsletter issue
+CREATE TABLE welcome_email(
+   -- there is only ever one row; it is updated, never inserted or deleted, by
+   -- the app
+   id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+   subject TEXT NOT NULL,
+   -- Markdown source, with the same placeholders as an issue
+   content TEXT NOT NULL,
+   -- off until an admin has looked at it (see `/admin/welcome`), so that
+   -- upgrading doesn't start sending an email nobody has written
+   enabled BOOLEAN NOT NULL DEFAULT false
+);
+
+INSERT INTO welcome_email (subject, content)
+VALUES (
+   'Welcome!',
+   E'Hi {{ name }},\n\nthanks for confirming your subscription! New issues will land in this inbox.\n'
+);
+
+-- null until the welcome email has been enqueued for the subscriber
+ALTER TABLE subscriptions ADD COLUMN welcomed_at timestamptz;
@@ -0,0 +1,15 @@
+-- transactional emails (e.g. confirmation links), inserted in the same
+-- transaction as whatever they refer to, and sent by the delivery worker with
+-- the same retries as `issue_delivery_queue`. unlike issues, they are rendered
+-- when enqueued, so a row is a complete email
+CREATE TABLE email_outbox(
+   email_id uuid PRIMARY KEY,
+   recipient TEXT NOT NULL,
+   subject TEXT NOT NULL,
+   html_content TEXT NOT NULL,
+   text_content TEXT NOT NULL,
+   -- see `issue_delivery_queue`
+   n_retries SMALLINT NOT NULL DEFAULT 0,
+   execute_after timestamptz NOT NULL DEFAULT now(),
+   enqueued_at timestamptz NOT NULL DEFAULT now()
+);
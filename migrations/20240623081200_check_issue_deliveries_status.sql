@@ -0,0 +1,7 @@
+-- the possible values of `issue_deliveries.status` (see `DeliveryStatus`):
+-- 'sent', 'skipped' (subscriber removed, or invalid email), 'bounced'
+-- (recipient rejected by the provider; the subscriber is marked as bounced
+-- too) or 'failed' (dead-lettered, see `failed_deliveries`)
+ALTER TABLE issue_deliveries
+   ADD CONSTRAINT issue_deliveries_status_check
+   CHECK (status IN ('sent', 'skipped', 'bounced', 'failed'));
@@ -1,6 +1,9 @@
 use std::env;
 use std::env::current_dir;
 use std::fmt::Display;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
 use config::Config;
@@ -12,7 +15,11 @@ use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::PgConnectOptions;
 
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::EmailProvider;
+use crate::email_client::FileClient;
+use crate::email_client::PostmarkClient;
+use crate::email_client::RateLimitedClient;
+use crate::email_client::SmtpClient;
 
 /// Global configuration, loaded from configuration.yaml. See
 /// `get_configuration`.
@@ -26,10 +33,26 @@ pub struct Settings {
 
     pub email_client: EmailClientSettings,
 
+    pub delivery: DeliverySettings,
+
     /// may be moved into a sub-struct
     pub redis_uri: Secret<String>,
 }
 
+/// Configuration of the delivery worker; see `DeliveryWorker`
+#[derive(Clone, Deserialize)]
+pub struct DeliverySettings {
+    /// Number of deliveries sent concurrently, all sharing the same email
+    /// client (and thus its rate limits, see `EmailClientSettings`)
+    #[serde(
+        default = "default_workers",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub workers: usize,
+}
+
+fn default_workers() -> usize { 4 }
+
 /// Server configuration
 #[derive(Clone, Deserialize)]
 pub struct ApplicationSettings {
@@ -46,6 +69,39 @@ pub struct ApplicationSettings {
 
     /// Required mainly for safe redirects on unsuccessful login
     pub hmac_secret: Secret<String>,
+
+    /// On SIGTERM, in-flight requests and deliveries are given this long to
+    /// finish, after which they are aborted (and rolled back); see `shutdown`
+    #[serde(
+        default = "default_shutdown_timeout_secs",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub shutdown_timeout_secs: u64,
+
+    /// Confirmation links sent to new subscribers stop working after this
+    /// long; see `ConfirmationTokenTtl`
+    #[serde(
+        default = "default_confirmation_token_ttl_hours",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub confirmation_token_ttl_hours: u64,
+
+    /// Re-read HTML templates from `src/routes/templates` on every render, so
+    /// that they can be edited without a rebuild. Only meant for local
+    /// development; see `Templates`.
+    #[serde(default)]
+    pub hot_reload_templates: bool,
+}
+
+fn default_shutdown_timeout_secs() -> u64 { 30 }
+fn default_confirmation_token_ttl_hours() -> u64 { 48 }
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> Duration { Duration::from_secs(self.shutdown_timeout_secs) }
+
+    pub fn confirmation_token_ttl(&self) -> Duration {
+        Duration::from_secs(self.confirmation_token_ttl_hours * 3600)
+    }
 }
 
 /// Database configuration
@@ -109,12 +165,93 @@ impl DatabaseSettings {
     }
 } //}}}
 
+/// Backend used to send emails, see `EmailClientSettings::client`
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProviderKind {
+    /// JSON over HTTP (`PostmarkClient`), using `base_url` and
+    /// `authorization_token`
+    #[default]
+    Postmark,
+    /// Requires `smtp`
+    Smtp,
+    /// Requires `file_dir`; nothing is actually sent
+    File,
+}
+
 #[derive(Clone, Deserialize)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub provider: EmailProviderKind,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_ms: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub file_dir: Option<PathBuf>,
+    /// Limits imposed by the provider, shared by everything that uses the
+    /// same client (i.e. the API and the delivery worker, see
+    /// `Application::email_client`); unlimited if not set. See
+    /// `RateLimitedClient`.
+    #[serde(default)]
+    pub max_per_second: Option<NonZeroU32>,
+    #[serde(default)]
+    pub max_per_hour: Option<NonZeroU32>,
+}
+
+/// Connection to a (typically self-hosted) SMTP server, see `SmtpClient`
+#[derive(Clone, Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    /// Usually 587 for `starttls`, 465 for `tls`, 25 for `none`
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    /// Tried in order; the first one offered by the server is used
+    #[serde(default = "default_auth_mechanisms")]
+    pub auth_mechanisms: Vec<SmtpAuthMechanism>,
+    /// Connections are kept open and reused across deliveries, up to this many
+    #[serde(
+        default = "default_max_connections",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub max_connections: u32,
+    /// Idle connections are closed after this long
+    #[serde(
+        default = "default_idle_timeout_secs",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub idle_timeout_secs: u64,
+}
+
+fn default_auth_mechanisms() -> Vec<SmtpAuthMechanism> {
+    vec![SmtpAuthMechanism::Plain, SmtpAuthMechanism::Login]
+}
+fn default_max_connections() -> u32 { 4 }
+fn default_idle_timeout_secs() -> u64 { 60 }
+
+/// How the SMTP connection is secured
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plaintext; only acceptable for a relay on localhost (or in tests)
+    None,
+    /// Connect in plaintext, then upgrade with `STARTTLS`, which the server
+    /// must support
+    #[default]
+    Starttls,
+    /// Implicit TLS, from the first byte
+    Tls,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpAuthMechanism {
+    Plain,
+    Login,
 }
 
 impl EmailClientSettings {
@@ -125,17 +262,49 @@ impl EmailClientSettings {
 
     pub fn timeout(&self) -> Duration { Duration::from_millis(self.timeout_ms) }
 
-    /// Create a separate worker for sending emails, outside the main API
+    /// Create the email client declared by `provider`, to be shared by the API
+    /// and the delivery worker. Panics if the settings required by `provider`
+    /// are missing.
+    ///
+    /// The client is always wrapped in a `RateLimitedClient`, so that provider
+    /// throttling is honoured even if no limits are configured.
     // copied from init_worker, for testing
-    pub fn client(self) -> EmailClient {
+    pub fn client(self) -> Arc<dyn EmailProvider> {
+        let (max_per_second, max_per_hour) = (self.max_per_second, self.max_per_hour);
+        Arc::new(RateLimitedClient::new(
+            self.provider_client(),
+            max_per_second,
+            max_per_hour,
+        ))
+    }
+
+    fn provider_client(self) -> Arc<dyn EmailProvider> {
         let sender_email = self.sender().unwrap();
-        let timeout = self.timeout();
-        EmailClient::new(
-            self.base_url,
-            sender_email,
-            self.authorization_token,
-            timeout,
-        )
+        match self.provider {
+            EmailProviderKind::Postmark => {
+                let timeout = self.timeout();
+                Arc::new(PostmarkClient::new(
+                    self.base_url,
+                    sender_email,
+                    self.authorization_token,
+                    timeout,
+                ))
+            }
+            EmailProviderKind::Smtp => {
+                let timeout = self.timeout();
+                let smtp = self.smtp.expect("`smtp` is required by the smtp provider");
+                Arc::new(
+                    SmtpClient::new(&smtp, sender_email, timeout)
+                        .expect("could not init SMTP client"),
+                )
+            }
+            EmailProviderKind::File => {
+                let dir = self
+                    .file_dir
+                    .expect("`file_dir` is required by the file provider");
+                Arc::new(FileClient::new(dir, sender_email).expect("could not create `file_dir`"))
+            }
+        }
     }
 }
 
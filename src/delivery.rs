@@ -1,19 +1,39 @@
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::DateTime;
+use chrono::Utc;
+use sqlx::postgres::PgListener;
 use sqlx::Executor;
 use sqlx::PgPool;
 use sqlx::Postgres;
 use sqlx::Transaction;
+use tokio::sync::futures::Notified;
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
 use uuid::Uuid;
 
 use crate::configuration::Settings;
+use crate::domain::IssueContext;
+use crate::domain::NewsletterContent;
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::EmailProvider;
+use crate::email_client::SendError;
+use crate::outbox::try_send_outbox_email;
+use crate::routes::error_chain_fmt;
+use crate::routes::unsubscribe_link;
+use crate::shutdown::Shutdown;
 use crate::startup::get_connection_pool;
+use crate::startup::HmacSecret;
 
 /// Not to be confused with `NewsletterForm`!
 pub struct Newsletter {
     title: String,
+    /// Markdown source, which may contain placeholders; see `IssueContext`
     content: String,
 }
 
@@ -36,8 +56,42 @@ async fn get_issue(
     Ok(issue)
 }
 
-/// To be run as a separate worker, outside the main API
-pub async fn init_delivery_worker(cfg: Settings) -> Result<(), anyhow::Error> {
+/// Recipient of a delivery, from `subscriptions`
+struct Subscriber {
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT email, name, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscriber)
+}
+
+/// To be run as a separate worker, outside the main API. Returns once
+/// `shutdown` is triggered, and in-flight deliveries have finished.
+///
+/// `email_client` should be the API's (see `Application::email_client`), so
+/// that both stay within the same rate limits.
+pub async fn init_delivery_worker(
+    cfg: Settings,
+    email_client: Arc<dyn EmailProvider>,
+    shutdown: Shutdown,
+) -> Result<(), anyhow::Error> {
     // let sender_email = cfg.email_client.sender().unwrap();
     // let timeout = cfg.email_client.timeout();
     // let email_client = EmailClient::new(
@@ -47,22 +101,236 @@ pub async fn init_delivery_worker(cfg: Settings) -> Result<(), anyhow::Error> {
     //     timeout,
     // );
 
-    let email_client = cfg.email_client.client();
-    let pool = get_connection_pool(&cfg.database);
-    send_email_loop(&pool, email_client).await
+    let worker = DeliveryWorker::new(
+        get_connection_pool(&cfg.database),
+        email_client,
+        cfg.application.base_url,
+        HmacSecret(cfg.application.hmac_secret),
+    );
+    worker.run(cfg.delivery.workers, shutdown).await
 }
 
-async fn send_email_loop(
-    pool: &PgPool,
-    email_client: EmailClient,
+/// Channel on which `notify_delivery_worker` announces new deliveries
+pub const DELIVERY_CHANNEL: &str = "issue_delivery_queue";
+
+/// Wake up idle delivery tasks, see `DeliveryWorker`. Postgres only sends the
+/// notification once `transaction` is committed (and not at all if it is rolled
+/// back), so the new deliveries are visible by the time the worker looks.
+pub async fn notify_delivery_worker(
+    transaction: &mut Transaction<'static, Postgres>
+) -> Result<(), sqlx::Error> {
+    // `NOTIFY` doesn't accept bind parameters
+    let query = sqlx::query!("SELECT pg_notify($1, '')", DELIVERY_CHANNEL);
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Polling interval of idle delivery tasks, when `DELIVERY_CHANNEL` cannot be
+/// listened to
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shortest wait of an idle delivery task; the queue may only contain
+/// deliveries that are being sent by other tasks
+const MIN_WAIT: Duration = Duration::from_secs(1);
+
+/// Shared by all delivery tasks; driven by `listen_loop`
+#[derive(Default)]
+struct Wakeup {
+    notify: Notify,
+    /// Unset until the listener has connected, and whenever its connection
+    /// drops, in which case idle tasks fall back to polling
+    listening: AtomicBool,
+}
+
+impl Wakeup {
+    fn set_listening(
+        &self,
+        listening: bool,
+    ) {
+        self.listening.store(listening, Ordering::SeqCst);
+        // notifications may have been missed while disconnected, and idle tasks
+        // need to switch between polling and waiting
+        self.notify.notify_waiters();
+    }
+
+    fn is_listening(&self) -> bool { self.listening.load(Ordering::SeqCst) }
+}
+
+/// Everything a delivery task needs; cheap to clone, so that every task gets
+/// its own copy of the same (shared) email client and pool. Sends are rate
+/// limited by the email client (see `RateLimitedClient`), across all tasks
+#[derive(Clone)]
+pub struct DeliveryWorker {
+    pool: PgPool,
+    email_client: Arc<dyn EmailProvider>,
+    base_url: String,
+    hmac_secret: HmacSecret,
+    wakeup: Arc<Wakeup>,
+}
+
+impl DeliveryWorker {
+    pub fn new(
+        pool: PgPool,
+        email_client: Arc<dyn EmailProvider>,
+        base_url: String,
+        hmac_secret: HmacSecret,
+    ) -> Self {
+        Self {
+            pool,
+            email_client,
+            base_url,
+            hmac_secret,
+            wakeup: Arc::new(Wakeup::default()),
+        }
+    }
+
+    /// Run `n_workers` concurrent delivery tasks until `shutdown` is triggered
+    /// (or one of them fails). Each task dequeues its own deliveries;
+    /// `start_delivery` makes sure that no delivery is picked up twice.
+    ///
+    /// Idle tasks are woken up by `notify_delivery_worker`, via a single
+    /// listener connection.
+    ///
+    /// On shutdown, a delivery that is being sent is allowed to finish; no new
+    /// ones are started.
+    pub async fn run(
+        self,
+        n_workers: usize,
+        shutdown: Shutdown,
+    ) -> Result<(), anyhow::Error> {
+        let mut tasks = JoinSet::new();
+        tasks.spawn(listen_loop(
+            self.pool.clone(),
+            self.wakeup.clone(),
+            shutdown.clone(),
+        ));
+        for _ in 0..n_workers.max(1) {
+            tasks.spawn(self.clone().send_email_loop(shutdown.clone()));
+        }
+        tracing::info!("started {} delivery tasks", tasks.len() - 1);
+
+        while let Some(outcome) = tasks.join_next().await {
+            outcome??;
+        }
+        tracing::info!("all delivery tasks stopped");
+        Ok(())
+    }
+
+    async fn send_email_loop(
+        self,
+        shutdown: Shutdown,
+    ) -> Result<(), anyhow::Error> {
+        while !shutdown.is_triggered() {
+            // registered before looking at the queue, so that a notification sent
+            // in between is not missed
+            let notified = self.wakeup.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            // not raced against `shutdown`; dropping this mid-send would roll back
+            // a delivery that may already have been sent
+            match try_send_email(
+                &self.pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            {
+                Err(_) => shutdown.sleep(Duration::from_secs(1)).await,
+                Ok(DeliveryOutcome::NoTasksLeft) => {
+                    tokio::select! {
+                        _ = self.wait_for_tasks(notified) => {}
+                        _ = shutdown.triggered() => {}
+                    }
+                }
+                Ok(DeliveryOutcome::TasksLeft) => {} // start next delivery immediately
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until new deliveries are enqueued, or a postponed one is due. Only
+    /// poll if nobody is listening for new deliveries.
+    async fn wait_for_tasks(
+        &self,
+        notified: Pin<&mut Notified<'_>>,
+    ) {
+        let timeout = match self.wakeup.is_listening() {
+            false => Some(POLL_INTERVAL),
+            true => match next_due(&self.pool).await {
+                Ok(Some(due)) => Some(
+                    (due - Utc::now())
+                        .to_std()
+                        .unwrap_or_default()
+                        .max(MIN_WAIT),
+                ),
+                // nothing to do until notified
+                Ok(None) => None,
+                Err(_) => Some(POLL_INTERVAL),
+            },
+        };
+        match timeout {
+            Some(timeout) => {
+                let _ = tokio::time::timeout(timeout, notified).await;
+            }
+            None => notified.await,
+        }
+    }
+}
+
+/// Listen on `DELIVERY_CHANNEL`, and wake up idle delivery tasks whenever
+/// something is enqueued. Reconnects (after a short delay) if the connection
+/// drops.
+async fn listen_loop(
+    pool: PgPool,
+    wakeup: Arc<Wakeup>,
+    shutdown: Shutdown,
 ) -> Result<(), anyhow::Error> {
-    loop {
-        match try_send_email(pool, &email_client).await {
-            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
-            Ok(DeliveryOutcome::NoTasksLeft) => tokio::time::sleep(Duration::from_secs(10)).await,
-            Ok(DeliveryOutcome::TasksLeft) => {} // start next delivery immediately
+    while !shutdown.is_triggered() {
+        tokio::select! {
+            outcome = listen(&pool, &wakeup) => match outcome {
+                Ok(()) => tracing::warn!("delivery listener connection lost, polling"),
+                Err(e) => tracing::warn!(e.cause_chain=?e, "delivery listener failed, polling"),
+            },
+            _ = shutdown.triggered() => break,
         }
+        wakeup.set_listening(false);
+        shutdown.sleep(Duration::from_secs(1)).await;
+    }
+    Ok(())
+}
+
+/// Returns when the connection is lost
+async fn listen(
+    pool: &PgPool,
+    wakeup: &Wakeup,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(DELIVERY_CHANNEL).await?;
+    wakeup.set_listening(true);
+
+    while listener.try_recv().await?.is_some() {
+        wakeup.notify.notify_waiters();
     }
+    Ok(())
+}
+
+/// When the next delivery (or outbox email) is due, if any
+async fn next_due(pool: &PgPool) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT min(execute_after) AS next_due
+        FROM (
+            SELECT execute_after FROM issue_delivery_queue
+            UNION ALL
+            SELECT execute_after FROM email_outbox
+        ) q
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.next_due)
 }
 
 pub enum DeliveryOutcome {
@@ -80,15 +348,26 @@ pub enum DeliveryOutcome {
 )]
 pub async fn try_send_email(
     pool: &PgPool,
-    email_client: &EmailClient,
+    email_client: &dyn EmailProvider,
+    // required for personalised unsubscribe links
+    base_url: &str,
+    hmac_secret: &HmacSecret,
 ) -> Result<DeliveryOutcome, anyhow::Error> {
+    // transactional emails (e.g. confirmation links) are being waited for, so
+    // they go before any issue
+    if try_send_outbox_email(pool, email_client).await? {
+        return Ok(DeliveryOutcome::TasksLeft);
+    }
+
     let task = start_delivery(pool).await?;
 
     if task.is_none() {
         return Ok(DeliveryOutcome::NoTasksLeft);
     }
 
-    let (mut transaction, issue_id, email) = task.unwrap();
+    let (transaction, task) = task.unwrap();
+    let issue_id = task.issue_id;
+    let email = task.subscriber_email.clone();
 
     tracing::Span::current()
         .record("issue_id", tracing::field::display(issue_id))
@@ -98,93 +377,187 @@ pub async fn try_send_email(
 
     let issue = get_issue(pool, issue_id).await?;
 
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            while let Err(e) = email_client
-                .send_email(&email, &issue.title, &issue.content, &issue.content)
-                .await
-            // // `with_context` is lazy, and is preferred when the context is
-            // // not static
-            // .with_context(|| format!("could not send newsletter to {}", email))
-            // .map_err(error_500)?, // "cannot be shared across threads"
-            {
-                tracing::error!(
+    // the subscriber may have been removed since the task was enqueued
+    let Some(subscriber) = get_subscriber(pool, task.subscriber_id).await? else {
+        tracing::warn!("skipping removed subscriber");
+        finish_delivery(transaction, &task, DeliveryStatus::Skipped, task.n_retries).await?;
+        return Ok(DeliveryOutcome::TasksLeft);
+    };
+
+    let (status, n_attempts) =
+        match SubscriberEmail::parse(subscriber.email) {
+            Ok(email) => {
+                let link = unsubscribe_link(base_url, task.subscriber_id, hmac_secret);
+                let context = IssueContext::new(subscriber.name, link, subscriber.subscribed_at);
+
+                let message_id =
+                    match send_issue(email_client, &email, &issue.title, &issue.content, &context)
+                        .await
+                    {
+                        Ok(message_id) => message_id,
+                        // templates are validated at publish time, so this should only fail if
+                        // the issue was inserted by other means. retrying won't help either way
+                        Err(SendIssueError::RenderError(e)) => {
+                            tracing::error!(e.cause_chain=?e, "failed to render issue");
+                            dead_letter(transaction, &task, task.n_retries + 1, &e.to_string())
+                                .await?;
+                            return Ok(DeliveryOutcome::TasksLeft);
+                        }
+                        // the provider is throttling us; this says nothing about the
+                        // recipient, so try again once the pause is over, without counting it
+                        // as a failure (the email client also holds back all other sends)
+                        Err(SendIssueError::SendError(SendError::RateLimited { retry_after })) => {
+                            let pause = retry_after.unwrap_or(SendError::DEFAULT_PAUSE);
+                            postpone_delivery(transaction, &task, pause).await?;
+                            return Ok(DeliveryOutcome::TasksLeft);
+                        }
+                        // retrying won't help, and may hurt our reputation with the provider
+                        Err(SendIssueError::SendError(SendError::PermanentRecipient(reason))) => {
+                            tracing::warn!("recipient rejected, marking as bounced: {reason}");
+                            bounce(transaction, &task, task.n_retries + 1).await?;
+                            return Ok(DeliveryOutcome::TasksLeft);
+                        }
+                        // says nothing about the recipient either, and would use up the
+                        // retries of every queued delivery. postpone without counting it, so
+                        // that deliveries resume on their own once the configuration has been
+                        // fixed (the email client holds back all other sends meanwhile)
+                        Err(SendIssueError::SendError(SendError::PermanentConfig(e))) => {
+                            tracing::error!(e.cause_chain=?e, "failed to deliver to {email}");
+                            postpone_delivery(transaction, &task, SendError::CONFIG_PAUSE).await?;
+                            return Ok(DeliveryOutcome::TasksLeft);
+                        }
+                        // // `with_context` is lazy, and is preferred when the context is
+                        // // not static
+                        // .with_context(|| format!("could not send newsletter to {}", email))
+                        // .map_err(error_500)?, // "cannot be shared across threads"
+                        Err(SendIssueError::SendError(e)) => {
+                            tracing::error!(
+                                e.cause_chain=?e,
+                                // e.message=%e,
+                                "failed to deliver to {email}"
+                            );
+
+                            // previously, we slept (and retried) right here, which kept the row
+                            // locked and the transaction open for as long as the provider was
+                            // down, stalling the worker. instead, record the failure and let the
+                            // task be picked up again once `execute_after` has passed; in the
+                            // meantime, the worker is free to move on to other recipients
+                            let retries = task.n_retries + 1;
+                            if retries > MAX_RETRIES {
+                                tracing::error!("aborting after {retries} retries!");
+                                dead_letter(transaction, &task, retries, &e.to_string()).await?;
+                            } else {
+                                schedule_retry(transaction, &task, retries).await?;
+                            }
+                            return Ok(DeliveryOutcome::TasksLeft);
+                        }
+                    };
+                let sent = DeliveryStatus::Sent { message_id };
+                (sent, task.n_retries + 1)
+            }
+
+            Err(e) => {
+                tracing::warn!(
                     e.cause_chain=?e,
                     // e.message=%e,
-                    "failed to deliver to {email}" //, retrying in {seconds} seconds..."
+                    "skipping invalid email"
                 );
+                (DeliveryStatus::Skipped, task.n_retries)
+            }
+        };
 
-                // everything below is beyond the scope of the book (and potentially
-                // unnecessary); i wanted to put it in a function, but `transaction` is very
-                // hard to pass around (we still need it for `finish_delivery`)
-
-                let row = sqlx::query!(
-                    r#"
-                        SELECT n_retries, execute_after
-                        FROM issue_delivery_queue
-                        WHERE
-                            newsletter_issue_id = $1 AND
-                            subscriber_email = $2
-                        "#,
-                    issue_id,
-                    email.as_ref()
-                )
-                .fetch_one(&mut *transaction)
-                .await?;
-
-                // i forgot to declare NOT NULL
-                let retries = row.n_retries.unwrap() + 1;
-                let seconds = retries * row.execute_after.unwrap();
-
-                if seconds > 5000 {
-                    return Err(anyhow::anyhow!("aborting after {retries} retries!"));
-                }
+    finish_delivery(transaction, &task, status, n_attempts).await?;
 
-                tokio::time::sleep(Duration::from_secs(seconds as u64)).await;
-
-                sqlx::query!(
-                    r#"
-                        UPDATE issue_delivery_queue
-                        SET
-                            n_retries = $1,
-                            execute_after = $2
-                        WHERE
-                            newsletter_issue_id = $3 AND
-                            subscriber_email = $4
-                        "#,
-                    retries,
-                    seconds,
-                    issue_id,
-                    email.as_ref()
-                )
-                .execute(&mut *transaction)
-                .await?;
-            }
-        }
+    Ok(DeliveryOutcome::TasksLeft)
+}
+
+#[derive(thiserror::Error)]
+pub enum SendIssueError {
+    #[error("Failed to render issue")]
+    RenderError(#[from] tera::Error),
+    #[error(transparent)]
+    SendError(#[from] SendError),
+}
 
-        Err(e) => tracing::warn!(
-            e.cause_chain=?e,
-            // e.message=%e,
-            "skipping invalid email"
-        ),
+impl Debug for SendIssueError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        error_chain_fmt(self, f)
     }
+}
 
-    finish_delivery(transaction, issue_id, &email).await?;
+/// Render an issue for a single recipient, with the unsubscribe link from
+/// `context` at the bottom. Also used for the welcome email (see
+/// `enqueue_welcome_email`).
+pub fn render_issue(
+    // Markdown source, see `NewsletterContent::from_template`
+    source: &str,
+    context: &IssueContext,
+) -> Result<NewsletterContent, tera::Error> {
+    let content = NewsletterContent::from_template(source, context)?;
+    let link = &context.unsubscribe_url;
+    Ok(NewsletterContent {
+        html: format!(r#"{}<p><a href="{link}">Unsubscribe</a></p>"#, content.html),
+        text: format!("{}\n\nUnsubscribe: {link}", content.text),
+    })
+}
 
-    Ok(DeliveryOutcome::TasksLeft)
+/// Render an issue for a single recipient (see `render_issue`), and send it.
+/// The `List-Unsubscribe` headers are taken from `context`. Returns the
+/// provider's message id, if any.
+///
+/// Also used by `send_test_issue`, so that test issues are identical to
+/// delivered ones.
+pub async fn send_issue(
+    email_client: &dyn EmailProvider,
+    recipient: &SubscriberEmail,
+    title: &str,
+    // Markdown source, see `NewsletterContent::from_template`
+    source: &str,
+    context: &IssueContext,
+) -> Result<Option<String>, SendIssueError> {
+    let content = render_issue(source, context)?;
+    let link = &context.unsubscribe_url;
+
+    // RFC 8058: mail clients may offer their own unsubscribe button, which
+    // `POST`s to the link (without any user interaction)
+    let list_unsubscribe = format!("<{link}>");
+    let headers = [
+        ("List-Unsubscribe", list_unsubscribe.as_str()),
+        ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+    ];
+
+    let message_id = email_client
+        .send_email(recipient, title, &content.html, &content.text, &headers)
+        .await?;
+    Ok(message_id)
 }
 
 type PgTransaction = Transaction<'static, Postgres>;
 
-/// Dequeue an entry in `issue_delivery_queue`
+/// A row in `issue_delivery_queue`
+struct DeliveryTask {
+    issue_id: Uuid,
+    subscriber_email: String,
+    subscriber_id: Uuid,
+    /// Number of failed attempts so far
+    n_retries: i16,
+    enqueued_at: DateTime<Utc>,
+}
+
+/// Dequeue an entry in `issue_delivery_queue`. Entries that are waiting to be
+/// retried (see `schedule_retry`) are skipped.
 async fn start_delivery(
     pool: &PgPool
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
     let query = sqlx::query!(
         r#"
-        SELECT newsletter_issue_id, subscriber_email
+        SELECT newsletter_issue_id, subscriber_email, subscriber_id, n_retries, enqueued_at
         FROM issue_delivery_queue
+        WHERE execute_after <= now()
 
         FOR UPDATE -- lock currently selected row
         SKIP LOCKED -- don't select currently locked rows
@@ -200,21 +573,177 @@ async fn start_delivery(
     //     .map(|r| (transaction, r.newsletter_issue_id, r.subscriber_email));
 
     // https://github.com/LukeMathWalker/zero-to-production/blob/a48a2a24720f820432a33b070c807b2f448b625f/src/issue_delivery_worker.rs#L89
-    let result = query
-        .fetch_optional(&mut *transaction)
-        .await?
-        .map(|r| (transaction, r.newsletter_issue_id, r.subscriber_email));
+    let result = query.fetch_optional(&mut *transaction).await?.map(|r| {
+        let task = DeliveryTask {
+            issue_id: r.newsletter_issue_id,
+            subscriber_email: r.subscriber_email,
+            subscriber_id: r.subscriber_id,
+            n_retries: r.n_retries,
+            enqueued_at: r.enqueued_at,
+        };
+        (transaction, task)
+    });
 
     Ok(result)
 }
 
-/// This is the last action in the transaction
+/// Number of failed attempts after which a delivery is given up
+pub(crate) const MAX_RETRIES: i16 = 10;
+
+/// Delay before the first retry; doubled with every subsequent retry, i.e. 2 s,
+/// 4 s, ..., ~17 min
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Delay before retry number `retries` (starting at 1)
+pub(crate) fn retry_delay(retries: i16) -> Duration {
+    BASE_RETRY_DELAY * 2_u32.pow(retries as u32 - 1)
+}
+
+/// Record a failed attempt, and postpone the next one with exponential
+/// backoff. Like `finish_delivery`, this is the last action in the
+/// transaction.
+async fn schedule_retry(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    retries: i16,
+) -> Result<(), anyhow::Error> {
+    let delay = retry_delay(retries);
+    let execute_after = Utc::now() + delay;
+    tracing::info!("retrying in {} seconds", delay.as_secs());
+
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = $1,
+            execute_after = $2
+        WHERE
+            newsletter_issue_id = $3 AND
+            subscriber_email = $4
+        "#,
+        retries,
+        execute_after,
+        task.issue_id,
+        task.subscriber_email,
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Postpone the next attempt by `delay`, without counting this one as failed
+/// (e.g. because the provider is rate limiting us). Like `finish_delivery`,
+/// this is the last action in the transaction.
+async fn postpone_delivery(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + delay;
+    tracing::info!("postponing delivery by {} seconds", delay.as_secs());
+
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = $1
+        WHERE
+            newsletter_issue_id = $2 AND
+            subscriber_email = $3
+        "#,
+        execute_after,
+        task.issue_id,
+        task.subscriber_email,
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Mark the subscriber as `bounced`, so that future issues are not enqueued
+/// for them, and log the delivery as such. Like `finish_delivery`, this is the
+/// last action in the transaction.
+async fn bounce(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    n_attempts: i16,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'bounced'
+        WHERE id = $1
+        "#,
+        task.subscriber_id,
+    );
+    transaction.execute(query).await?;
+    finish_delivery(transaction, task, DeliveryStatus::Bounced, n_attempts).await
+}
+
+/// Move a task that has failed too many times from `issue_delivery_queue` to
+/// `failed_deliveries`, where it can be inspected (and retried or discarded) by
+/// an admin. Like `finish_delivery`, this is the last action in the
+/// transaction.
+async fn dead_letter(
+    mut transaction: PgTransaction,
+    task: &DeliveryTask,
+    n_attempts: i16,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO failed_deliveries
+            (newsletter_issue_id, subscriber_email, n_attempts, last_error, failed_at)
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_attempts = EXCLUDED.n_attempts,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.issue_id,
+        task.subscriber_email,
+        n_attempts,
+        last_error,
+    );
+    transaction.execute(query).await?;
+    finish_delivery(transaction, task, DeliveryStatus::Failed, n_attempts).await
+}
+
+/// Final state of a delivery, as recorded in `issue_deliveries`. The table
+/// only accepts the values of `as_str`, so a new variant needs a migration.
+#[derive(Debug, PartialEq)]
+enum DeliveryStatus {
+    Sent {
+        /// See `EmailProvider::send_email`
+        message_id: Option<String>,
+    },
+    /// The subscriber was removed, or their email is no longer valid
+    Skipped,
+    /// Rejected by the provider for good; see `bounce`
+    Bounced,
+    /// Given up after too many retries; see `dead_letter`
+    Failed,
+}
+
+impl DeliveryStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sent { .. } => "sent",
+            Self::Skipped => "skipped",
+            Self::Bounced => "bounced",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// Remove the task from the queue, and record its outcome in
+/// `issue_deliveries`. This is the last action in the transaction.
 async fn finish_delivery(
     // https://users.rust-lang.org/t/solved-placement-of-mut-in-function-parameters/19891
     mut transaction: PgTransaction, // mutable transaction
     // transaction: &mut PgTransaction, // mutable reference
-    issue_id: Uuid,
-    subscriber_email: &str,
+    task: &DeliveryTask,
+    status: DeliveryStatus,
+    n_attempts: i16,
 ) -> Result<(), anyhow::Error> {
     let query = sqlx::query!(
         r#"
@@ -223,8 +752,48 @@ async fn finish_delivery(
             newsletter_issue_id = $1 AND
             subscriber_email = $2
         "#,
-        issue_id,
-        subscriber_email
+        task.issue_id,
+        task.subscriber_email,
+    );
+    transaction.execute(query).await?;
+
+    let message_id = match &status {
+        DeliveryStatus::Sent { message_id } => message_id.as_deref(),
+        _ => None,
+    };
+
+    // a failed delivery may be retried by an admin, after which it is logged
+    // again
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries
+        (
+            newsletter_issue_id,
+            subscriber_id,
+            subscriber_email,
+            status,
+            n_attempts,
+            provider_message_id,
+            enqueued_at,
+            finished_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            subscriber_id = EXCLUDED.subscriber_id,
+            status = EXCLUDED.status,
+            n_attempts = EXCLUDED.n_attempts,
+            provider_message_id = EXCLUDED.provider_message_id,
+            enqueued_at = EXCLUDED.enqueued_at,
+            finished_at = EXCLUDED.finished_at
+        "#,
+        task.issue_id,
+        task.subscriber_id,
+        task.subscriber_email,
+        status.as_str(),
+        n_attempts,
+        message_id,
+        task.enqueued_at,
     );
     transaction.execute(query).await?;
     transaction.commit().await?;
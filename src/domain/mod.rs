@@ -1,7 +1,10 @@
 mod new_subscriber;
+mod newsletter_content;
 mod subscriber_email;
 mod subscriber_name;
 // allow external `use` statements to skip `new_subscriber` etc
 pub use new_subscriber::NewSubscriber;
+pub use newsletter_content::IssueContext;
+pub use newsletter_content::NewsletterContent;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
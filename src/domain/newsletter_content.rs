@@ -0,0 +1,288 @@
+use chrono::DateTime;
+use chrono::Utc;
+use pulldown_cmark::html::push_html;
+use pulldown_cmark::Event;
+use pulldown_cmark::Options;
+use pulldown_cmark::Parser;
+use pulldown_cmark::Tag;
+use pulldown_cmark::TagEnd;
+use serde::Serialize;
+use tera::Context;
+use tera::Tera;
+
+/// The two bodies of an issue, both rendered from the same Markdown source (as
+/// written by the admin). Rendering is done once, at publish time; the source
+/// itself is stored separately.
+#[derive(Debug)]
+pub struct NewsletterContent {
+    /// Sanitised, so it is safe to embed in our own pages (and emails)
+    pub html: String,
+    /// For mail clients that don't render (or users who don't want) HTML
+    pub text: String,
+}
+
+fn options() -> Options { Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES }
+
+/// Per-recipient values available to the Markdown source as Tera
+/// placeholders, e.g. `Hi {{ name }}`
+#[derive(Serialize)]
+pub struct IssueContext {
+    pub name: String,
+    pub unsubscribe_url: String,
+    /// RFC 3339, so that it can be formatted with Tera's `date` filter, e.g.
+    /// `{{ subscribed_at | date(format="%B %Y") }}`
+    pub subscribed_at: String,
+}
+
+impl IssueContext {
+    pub fn new(
+        name: String,
+        unsubscribe_url: String,
+        subscribed_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            name,
+            unsubscribe_url,
+            subscribed_at: subscribed_at.to_rfc3339(),
+        }
+    }
+
+    /// Stand-in values, for validating and previewing templates
+    pub fn example() -> Self {
+        Self::new(
+            "Jane Doe".to_string(),
+            "https://example.com/subscriptions/unsubscribe".to_string(),
+            Utc::now(),
+        )
+    }
+
+    /// Values for the public archive, where there is no recipient. There is
+    /// nothing to unsubscribe from either, so the link points to `home_url`.
+    pub fn public(
+        home_url: String,
+        published_at: DateTime<Utc>,
+    ) -> Self {
+        Self::new("reader".to_string(), home_url, published_at)
+    }
+}
+
+impl NewsletterContent {
+    pub fn from_markdown(source: &str) -> Self {
+        Self {
+            html: to_html(source),
+            text: to_text(source),
+        }
+    }
+
+    /// Fill in the placeholders in `source`, then render it as Markdown. The
+    /// template is applied to the source (rather than the rendered HTML) so
+    /// that placeholders can be used in link targets, which would otherwise be
+    /// percent-encoded.
+    ///
+    /// Fails if `source` is not a valid template, or uses an unknown variable.
+    pub fn from_template(
+        source: &str,
+        context: &IssueContext,
+    ) -> Result<Self, tera::Error> {
+        let context = Context::from_serialize(context)?;
+        // values are sanitised along with the rest of the HTML; escaping them
+        // here would also escape the plain text version
+        let source = Tera::one_off(source, &context, false)?;
+        Ok(Self::from_markdown(&source))
+    }
+}
+
+/// Markdown allows raw HTML, so the output must be sanitised; we trust admins,
+/// but not their clipboards
+fn to_html(source: &str) -> String {
+    let mut html = String::new();
+    push_html(&mut html, Parser::new_ext(source, options()));
+    ammonia::clean(&html)
+}
+
+/// Strip all formatting, keeping only the text, with paragraphs separated by
+/// blank lines. Links are written out in full, since they can't be clicked.
+/// Raw HTML is dropped.
+fn to_text(source: &str) -> String {
+    let mut text = String::new();
+    // `None` for unordered lists, otherwise the next item number
+    let mut lists: Vec<Option<u64>> = vec![];
+    let mut links: Vec<String> = vec![];
+
+    let end_block = |text: &mut String| {
+        let trimmed = text.trim_end_matches('\n').len();
+        text.truncate(trimmed);
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+    };
+
+    for event in Parser::new_ext(source, options()) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => {
+                end_block(&mut text);
+                text.push_str("---");
+                end_block(&mut text);
+            }
+            Event::Start(Tag::List(start)) => {
+                // nested lists start on their own line
+                if !lists.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    end_block(&mut text);
+                }
+            }
+            Event::Start(Tag::Item) => {
+                let indent = "  ".repeat(lists.len().saturating_sub(1));
+                let marker = match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    }
+                    _ => "-".to_string(),
+                };
+                text.push_str(&format!("{indent}{marker} "));
+            }
+            Event::End(TagEnd::Item) if !text.ends_with('\n') => text.push('\n'),
+            Event::Start(Tag::Link { dest_url, .. }) => links.push(dest_url.to_string()),
+            Event::End(TagEnd::Link) => {
+                if let Some(url) = links.pop() {
+                    if !text.ends_with(&url) {
+                        text.push_str(&format!(" ({url})"));
+                    }
+                }
+            }
+            Event::End(TagEnd::TableCell) => text.push('\t'),
+            Event::End(TagEnd::TableHead | TagEnd::TableRow) => {
+                let trimmed = text.trim_end_matches('\t').len();
+                text.truncate(trimmed);
+                text.push('\n');
+            }
+            // paragraphs in list items are not separated
+            Event::End(
+                TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::Table,
+            ) if lists.is_empty() => end_block(&mut text),
+            _ => {}
+        }
+    }
+
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_err;
+
+    use super::IssueContext;
+    use super::NewsletterContent;
+
+    #[test]
+    fn markdown_to_html() {
+        let content = NewsletterContent::from_markdown("# Hello\n\nSome *emphasis*, `code`.");
+        assert_eq!(
+            content.html,
+            "<h1>Hello</h1>\n<p>Some <em>emphasis</em>, <code>code</code>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn html_is_sanitised() {
+        let content = NewsletterContent::from_markdown(
+            "foo <script>alert(1)</script>\n\n<a href=\"javascript:alert(1)\" onclick=\"alert(1)\">bar</a>",
+        );
+        assert!(!content.html.contains("<script"));
+        assert!(!content.html.contains("javascript:"));
+        assert!(!content.html.contains("onclick"));
+        assert!(content.html.contains("bar"));
+    }
+
+    #[test]
+    fn markdown_to_text() {
+        let source = "\
+# Hello
+
+Some *emphasis*, **strong** and `code`,
+over two lines.
+
+- one
+- [two](https://foo.com)
+  1. three
+  2. <https://bar.com>
+
+---
+
+<div>raw html</div>
+
+Bye";
+        let content = NewsletterContent::from_markdown(source);
+        assert_eq!(
+            content.text,
+            "\
+Hello
+
+Some emphasis, strong and code,
+over two lines.
+
+- one
+- two (https://foo.com)
+  1. three
+  2. https://bar.com
+
+---
+
+Bye"
+        );
+    }
+
+    #[test]
+    fn template_placeholders() {
+        let context = IssueContext {
+            name: "Bob".to_string(),
+            unsubscribe_url: "https://foo.com/unsubscribe?token=abc".to_string(),
+            subscribed_at: "2024-06-01T12:00:00+00:00".to_string(),
+        };
+        let content = NewsletterContent::from_template(
+            "Hi {{ name }}, subscribed since {{ subscribed_at | date(format=\"%B %Y\") }}.\n\n[Unsubscribe]({{ unsubscribe_url }})",
+            &context,
+        )
+        .unwrap();
+        assert_eq!(
+            content.html,
+            "<p>Hi Bob, subscribed since June 2024.</p>\n<p><a href=\"https://foo.com/unsubscribe?token=abc\" rel=\"noopener noreferrer\">Unsubscribe</a></p>\n"
+        );
+        assert_eq!(
+            content.text,
+            "Hi Bob, subscribed since June 2024.\n\nUnsubscribe (https://foo.com/unsubscribe?token=abc)"
+        );
+    }
+
+    #[test]
+    fn template_values_are_sanitised() {
+        let mut context = IssueContext::example();
+        context.name = "<script>alert(1)</script>".to_string();
+        let content = NewsletterContent::from_template("Hi {{ name }}", &context).unwrap();
+        assert!(!content.html.contains("<script>"));
+    }
+
+    #[test]
+    fn invalid_template() {
+        for source in [
+            "{{ name",
+            "{% if name %}",
+            "{{ email }}",
+            "{{ name | foo }}",
+        ] {
+            assert_err!(
+                NewsletterContent::from_template(source, &IssueContext::example()),
+                "{source}"
+            );
+        }
+    }
+}
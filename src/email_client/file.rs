@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use lettre::AsyncFileTransport;
+use lettre::AsyncTransport;
+use lettre::Tokio1Executor;
+
+use super::mime::message_id;
+use super::mime::mime_message;
+use super::EmailProvider;
+use super::SendError;
+use crate::domain::SubscriberEmail;
+
+/// Writes every email to `<dir>/<uuid>.eml` instead of sending it, for local
+/// development without any provider. The files can be opened with any mail
+/// client.
+pub struct FileClient {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl FileClient {
+    /// `dir` is created if it does not exist
+    pub fn new(
+        dir: PathBuf,
+        sender: SubscriberEmail,
+    ) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            transport: AsyncFileTransport::new(dir),
+            sender,
+        })
+    }
+}
+
+#[async_trait]
+impl EmailProvider for FileClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Option<String>, SendError> {
+        let message = mime_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+        let message_id = message_id(&message);
+        // most likely a full disk, or a permission problem
+        let id = self
+            .transport
+            .send(message)
+            .await
+            .map_err(|e| SendError::Transient(e.into()))?;
+        tracing::info!("wrote email {id} to disk");
+        Ok(message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+    use uuid::Uuid;
+
+    use super::FileClient;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailProvider;
+
+    fn email() -> SubscriberEmail { SubscriberEmail::parse(SafeEmail().fake()).unwrap() }
+
+    #[tokio::test]
+    async fn send_email_writes_eml_file() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let client = FileClient::new(dir.clone(), email()).unwrap();
+        let recipient = email();
+
+        let message_id = assert_ok!(
+            client
+                .send_email(
+                    &recipient,
+                    "foo",
+                    "<p>bar</p>",
+                    "bar",
+                    &[("List-Unsubscribe", "<http://foo>")]
+                )
+                .await
+        )
+        .unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let eml = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(eml.contains(&format!("To: {}", recipient.as_ref())));
+        assert!(eml.contains("Subject: foo"));
+        assert!(eml.contains("List-Unsubscribe: <http://foo>"));
+        assert!(eml.contains("multipart/alternative"));
+        assert!(eml.contains(&format!("Message-ID: {message_id}")));
+        assert!(eml.contains("<p>bar</p>"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
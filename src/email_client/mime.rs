@@ -0,0 +1,82 @@
+use anyhow::Context;
+use lettre::message::header::ContentType;
+use lettre::message::header::HeaderName;
+use lettre::message::header::HeaderValue;
+use lettre::message::Mailbox;
+use lettre::message::MultiPart;
+use lettre::message::SinglePart;
+use lettre::Message;
+use uuid::Uuid;
+
+use super::SendError;
+use crate::domain::SubscriberEmail;
+
+/// Build a `multipart/alternative` MIME message, for the backends that don't
+/// go through a REST API (`SmtpClient`, `FileClient`). See `message_id`.
+///
+/// Fails with `SendError::PermanentRecipient` if lettre can't parse the
+/// recipient (which should have been caught by `SubscriberEmail::parse`
+/// already), and `SendError::PermanentConfig` for anything else.
+pub(super) fn mime_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[(&str, &str)],
+) -> Result<Message, SendError> {
+    let to = recipient
+        .as_ref()
+        .parse()
+        .map_err(|e| SendError::PermanentRecipient(format!("Invalid recipient: {e}")))?;
+    build_message(sender, to, subject, html_content, text_content, headers)
+        .map_err(SendError::PermanentConfig)
+}
+
+fn build_message(
+    sender: &SubscriberEmail,
+    to: Mailbox,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[(&str, &str)],
+) -> Result<Message, anyhow::Error> {
+    let mut builder = Message::builder()
+        .from(sender.as_ref().parse().context("Invalid sender")?)
+        .to(to)
+        .subject(subject)
+        // generated by us, since there is no provider to assign one
+        .message_id(Some(format!(
+            "<{}@{}>",
+            Uuid::new_v4(),
+            sender.as_ref().rsplit('@').next().unwrap_or("localhost"),
+        )));
+
+    for (name, value) in headers {
+        let name = HeaderName::new_from_ascii(name.to_string())
+            .with_context(|| format!("Invalid header name: {name}"))?;
+        builder = builder.raw_header(HeaderValue::new(name, value.to_string()));
+    }
+
+    // clients are expected to display the last part they support, so the
+    // plain text version goes first
+    let message = builder.multipart(
+        MultiPart::alternative()
+            .singlepart(
+                SinglePart::builder()
+                    .header(ContentType::TEXT_PLAIN)
+                    .body(text_content.to_string()),
+            )
+            .singlepart(
+                SinglePart::builder()
+                    .header(ContentType::TEXT_HTML)
+                    .body(html_content.to_string()),
+            ),
+    )?;
+    Ok(message)
+}
+
+/// The `Message-ID` header set by `mime_message`
+pub(super) fn message_id(message: &Message) -> Option<String> {
+    message.headers().get_raw("Message-ID").map(str::to_string)
+}
@@ -0,0 +1,88 @@
+//! Outgoing email. Callers (`delivery`, `routes::subscriptions`) only ever see
+//! `dyn EmailProvider`; which backend is used is decided by
+//! `EmailClientSettings::client`.
+
+mod file;
+mod mime;
+mod postmark;
+mod rate_limit;
+mod smtp;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use async_trait::async_trait;
+pub use file::*;
+pub use postmark::*;
+pub use rate_limit::*;
+pub use smtp::*;
+
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+
+// async fns in traits are stable, but are not object safe (yet), so
+// `#[async_trait]` is still required for `dyn EmailProvider`
+
+/// Anything that can deliver an email to a single recipient
+#[async_trait]
+pub trait EmailProvider: Send + Sync {
+    /// `headers` are `(name, value)` pairs, added to the email itself (e.g.
+    /// `List-Unsubscribe`).
+    ///
+    /// Returns the id assigned to the email by the provider (if it reports
+    /// one), for correlating with the provider's own logs.
+    ///
+    /// Fails if the email could not be handed over to the provider; an `Ok`
+    /// does not imply that the email actually reached the recipient's
+    /// inbox. See `SendError` for what the caller should do about it.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Option<String>, SendError>;
+}
+
+/// Why an email could not be sent, as far as the caller is concerned: whether
+/// (and when) it is worth trying again. Each provider derives this from its own
+/// status codes and error bodies.
+#[derive(thiserror::Error)]
+pub enum SendError {
+    /// Network errors, timeouts, server errors; likely to succeed if retried
+    /// later
+    #[error(transparent)]
+    Transient(anyhow::Error),
+    /// The provider asks us to slow down (e.g. `429 Too Many Requests`). This
+    /// says nothing about the email itself, so it should be sent again once
+    /// `retry_after` (if known) has passed, without counting as a failure.
+    #[error("Rate limited by provider")]
+    RateLimited { retry_after: Option<Duration> },
+    /// The recipient can never be delivered to (invalid address, hard bounce,
+    /// blocked by the provider); retrying is pointless
+    #[error("Recipient rejected: {0}")]
+    PermanentRecipient(String),
+    /// Our request was rejected (bad credentials, unverified sender, ...);
+    /// this affects every email, and retrying won't help until the
+    /// configuration is fixed
+    #[error(transparent)]
+    PermanentConfig(anyhow::Error),
+}
+
+impl SendError {
+    /// Used when a rate limited provider doesn't say how long to wait
+    pub const DEFAULT_PAUSE: Duration = Duration::from_secs(60);
+    /// How long to hold back after a `PermanentConfig` error; nothing will go
+    /// through until somebody fixes the settings, so there is no point in
+    /// trying again sooner
+    pub const CONFIG_PAUSE: Duration = Duration::from_secs(5 * 60);
+}
+
+impl Debug for SendError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
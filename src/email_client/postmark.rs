@@ -0,0 +1,493 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::header::RETRY_AFTER;
+use reqwest::Client;
+use reqwest::StatusCode;
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use secrecy::Secret;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::rate_limit::parse_retry_after;
+use super::EmailProvider;
+use super::SendError;
+use crate::domain::SubscriberEmail;
+
+/// Sends emails as JSON over HTTP, in the shape expected by Postmark's REST
+/// API. We use MailChimp since I don't have an email I can use with Postmark.
+//
+// https://github.com/LukeMathWalker/zero-to-production/issues/176#issuecomment-1490392528
+pub struct PostmarkClient {
+    /// The client that actually communicates with the REST API
+    http_client: Client,
+    /// In prod, this will depend on email provider. Locally, this is just
+    /// `localhost`.
+    base_url: String,
+    sender: SubscriberEmail,
+    /// API key from the email provider
+    authorization_token: Secret<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")] // PascalCase is required (?) by Postmark
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+/// Only the fields we use; the rest of the response is ignored
+#[derive(Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+}
+
+/// Body of a `422 Unprocessable Entity`
+/// https://postmarkapp.com/developer/api/overview#error-codes
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(rename = "ErrorCode")]
+    error_code: u32,
+    #[serde(rename = "Message")]
+    message: String,
+}
+
+/// "Inactive recipient": the address has hard bounced before, or marked us as
+/// spam, and Postmark won't send to it anymore
+const INACTIVE_RECIPIENT: u32 = 406;
+/// "Invalid email request"; only about the recipient if the message says so
+/// (e.g. "Error parsing 'To': Illegal email address 'foo'")
+const INVALID_EMAIL_REQUEST: u32 = 300;
+
+/// Decide whether an error response is worth retrying. Server errors are
+/// assumed to be temporary; client errors are permanent, and blamed on the
+/// recipient only if the error body says so.
+fn classify_error(
+    status: StatusCode,
+    body: &str,
+    e: reqwest::Error,
+) -> SendError {
+    if status.is_server_error() || status == StatusCode::REQUEST_TIMEOUT {
+        return SendError::Transient(e.into());
+    }
+    match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(r) if r.error_code == INACTIVE_RECIPIENT => SendError::PermanentRecipient(r.message),
+        Ok(r) if r.error_code == INVALID_EMAIL_REQUEST && r.message.contains("'To'") => {
+            SendError::PermanentRecipient(r.message)
+        }
+        Ok(r) => SendError::PermanentConfig(
+            anyhow::Error::new(e).context(format!("{} (code {})", r.message, r.error_code)),
+        ),
+        Err(_) => SendError::PermanentConfig(e.into()),
+    }
+}
+
+/// Custom header, e.g. `List-Unsubscribe`. Postmark expects these as a list of
+/// objects, not a map.
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+// establishing a HTTP connection is expensive, so if multiple requests are to
+// be sent to the same server, the connection should be reused. this can be done
+// by keeping `Client` at the top-level (App) and referencing (extracting) it
+// from the App
+//
+// `Client::clone`
+
+impl PostmarkClient {
+    /// `timeout` is not exposed at all, so it must be set via configuration. It
+    /// is only overridden for tests (to use a small value).
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            // enforce client-wide timeout
+            http_client: Client::builder()
+                // .timeout(Duration::from_secs(5))
+                .timeout(timeout)
+                .build()
+                .unwrap(),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailProvider for PostmarkClient {
+    /// Use the API provider to send an email. The path `/email` is used by
+    /// Postmark:
+    ///     https://postmarkapp.com/developer/user-guide/send-email-with-api#send-a-single-email
+    ///
+    /// Fails with `SendError::PermanentRecipient` if Postmark refuses the
+    /// recipient (see `classify_error`).
+    ///
+    /// `headers` are `(name, value)` pairs, added to the email itself (not the
+    /// HTTP request).
+    ///
+    /// I don't fully understand how this works, but it seems to work fine for
+    /// localhost+mock tests.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Option<String>, SendError> {
+        // SMTP and REST can be used to send email; REST is usually easier to set up,
+
+        // mailchimp doesn't seem to have an exact equivalent, so we roll with it for
+        // now until we inevitably run into problems
+        // https://mailchimp.com/developer/marketing/api/campaigns/
+        //
+        // "Send test email"
+        // POST /campaigns/{campaign_id}/actions/test
+        let url = format!("{}/email", self.base_url);
+        let url = Url::parse(&url).unwrap();
+        println!("sending POST request to {:?}", url.as_str());
+
+        let body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers: headers
+                .iter()
+                .map(|(name, value)| EmailHeader { name, value })
+                .collect(),
+        };
+
+        // `.json` accepts structs (which implement `Serialize`), and also sets the
+        // appropriate `Content-Type` header; `.body` doesn't
+        let resp = self
+            .http_client
+            .post(url)
+            // on Postmark this is "X-Postmark-Server-Token"
+            // https://mailchimp.com/developer/transactional/guides/send-first-email/#send-your-first-email
+            .header("key", self.authorization_token.expose_secret())
+            .json(&body)
+            .send()
+            .await
+            // connection errors and timeouts
+            .map_err(|e| SendError::Transient(e.into()))?;
+
+        // throttling is not a failure of this particular email; the caller is
+        // expected to back off and try again later
+        if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = resp
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after);
+            return Err(SendError::RateLimited { retry_after });
+        }
+        if let Err(e) = resp.error_for_status_ref() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(classify_error(status, &body, e));
+        }
+
+        // the email has been accepted at this point, so a missing (or unexpected)
+        // body is not an error
+        let message_id = resp
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .map(|r| r.message_id);
+        Ok(message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claims::assert_err;
+    use claims::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::Paragraph;
+    use fake::faker::lorem::en::Sentence;
+    use fake::Fake;
+    use fake::Faker;
+    use secrecy::Secret;
+    use serde_json::Value;
+    use wiremock::matchers::any;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::matchers::header;
+    use wiremock::matchers::header_exists;
+    use wiremock::matchers::method;
+    use wiremock::matchers::path;
+    use wiremock::Match;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
+
+    use super::PostmarkClient;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailProvider;
+    use crate::email_client::SendError;
+
+    struct SendEmailBodyMatcher;
+    impl Match for SendEmailBodyMatcher {
+        fn matches(
+            &self,
+            request: &wiremock::Request,
+        ) -> bool {
+            let parsed: Value = match serde_json::from_slice(&request.body) {
+                Ok(p) => p,
+                Err(_) => return false,
+            };
+
+            for key in ["From", "To", "Subject", "HtmlBody", "TextBody"] {
+                if parsed.get(key).is_none() {
+                    return false;
+                }
+            }
+            true
+        }
+    }
+
+    // random inputs are used (over hardcoded values) to signify that these inputs
+    // don't matter
+    fn email() -> SubscriberEmail { SubscriberEmail::parse(SafeEmail().fake()).unwrap() }
+    fn subject() -> String { Sentence(1..2).fake() }
+    fn content() -> String { Paragraph(1..2).fake() }
+
+    fn email_client(url: String) -> PostmarkClient {
+        PostmarkClient::new(
+            url,
+            email(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        )
+    }
+
+    // requires `rt` feature (?)
+    #[tokio::test]
+    async fn send_email_returns_200() {
+        // start mock server on random port. its base_url, accessed by `.uri()`, is used
+        // to init the email_client
+        let mock_server = MockServer::start().await;
+        let sender = email_client(mock_server.uri());
+
+        // must be declared before .send_email (which is somewhat unintuitive). this
+        // explains the use of `await`
+        Mock::given(
+            // // respond to any request with 200; restrictions can be imposed
+            // any(),
+            header_exists("key"),
+        )
+        .and(header("Content-Type", "application/json"))
+        .and(path("/email"))
+        .and(method("POST"))
+        .and(SendEmailBodyMatcher)
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1) // the actual assertion: expect email_client to receive 1 request
+        .mount(&mock_server)
+        .await;
+
+        // mock's test output isn't terribly helpful; it doesn't show expected/actual
+        // result
+
+        assert_ok!(
+            sender
+                .send_email(&email(), &subject(), &content(), &content(), &[])
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers() {
+        let mock_server = MockServer::start().await;
+        let sender = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{ "Name": "List-Unsubscribe", "Value": "<http://foo>" }]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(
+            sender
+                .send_email(
+                    &email(),
+                    &subject(),
+                    &content(),
+                    &content(),
+                    &[("List-Unsubscribe", "<http://foo>")]
+                )
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_message_id() {
+        let mock_server = MockServer::start().await;
+        let sender = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "foo@bar.com",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                "ErrorCode": 0,
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let message_id = sender
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            message_id.as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_500() {
+        let mock_server = MockServer::start().await;
+        let sender = email_client(mock_server.uri());
+
+        Mock::given(any()) // respond to any request
+            .respond_with(ResponseTemplate::new(500)) // simulate a 'server error'
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_err!(
+            sender
+                .send_email(&email(), &subject(), &content(), &content(), &[])
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_429() {
+        let mock_server = MockServer::start().await;
+        let sender = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let e = sender
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e,
+            SendError::RateLimited {
+                retry_after: Some(d)
+            } if d == Duration::from_secs(30)
+        ));
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_transient() {
+        let mock_server = MockServer::start().await;
+        let sender = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let e = sender
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await
+            .unwrap_err();
+        assert!(matches!(e, SendError::Transient(_)));
+    }
+
+    #[tokio::test]
+    async fn inactive_recipient_is_permanent() {
+        let mock_server = MockServer::start().await;
+        let sender = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                "ErrorCode": 406,
+                "Message": "You tried to send to a recipient that has been marked as inactive.",
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let e = sender
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await
+            .unwrap_err();
+        assert!(matches!(e, SendError::PermanentRecipient(_)));
+    }
+
+    #[tokio::test]
+    async fn other_client_errors_are_config_errors() {
+        for (status, body) in [
+            (
+                401,
+                serde_json::json!({ "ErrorCode": 10, "Message": "Bad or missing API token" }),
+            ),
+            (
+                422,
+                serde_json::json!({ "ErrorCode": 400, "Message": "Sender signature not defined" }),
+            ),
+        ] {
+            let mock_server = MockServer::start().await;
+            let sender = email_client(mock_server.uri());
+
+            Mock::given(any())
+                .respond_with(ResponseTemplate::new(status).set_body_json(body))
+                .mount(&mock_server)
+                .await;
+
+            let e = sender
+                .send_email(&email(), &subject(), &content(), &content(), &[])
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(e, SendError::PermanentConfig(_)),
+                "{status}: {e:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_timeout() {
+        let mock_server = MockServer::start().await;
+        let sender = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1800))) // simulate timeout
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_err!(
+            sender
+                .send_email(&email(), &subject(), &content(), &content(), &[])
+                .await
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use actix_web::http::header::HttpDate;
+use async_trait::async_trait;
+
+use super::EmailProvider;
+use super::SendError;
+use crate::domain::SubscriberEmail;
+
+/// `Retry-After` is either a number of seconds, or an HTTP date
+/// https://httpwg.org/specs/rfc9110.html#field.retry-after
+pub(super) fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date: SystemTime = value.parse::<HttpDate>().ok()?.into();
+    // a date in the past means "now"
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Holds up to `capacity` tokens, refilled continuously at `capacity` per
+/// `period`; every send takes one
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    /// Tokens per second
+    refill_rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Starts full, so the first `capacity` sends go out immediately
+    fn new(
+        capacity: NonZeroU32,
+        period: Duration,
+        now: Instant,
+    ) -> Self {
+        let capacity = capacity.get() as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_rate: capacity / period.as_secs_f64(),
+            last_refill: now,
+        }
+    }
+
+    fn refill(
+        &mut self,
+        now: Instant,
+    ) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.refill_rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// How long until a token is available; zero if one is available now
+    fn wait_time(
+        &mut self,
+        now: Instant,
+    ) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.refill_rate)
+        }
+    }
+
+    fn take(&mut self) { self.tokens -= 1.0; }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    buckets: Vec<TokenBucket>,
+    /// Set when the provider responds with `SendError::RateLimited` or
+    /// `SendError::PermanentConfig`
+    paused_until: Option<Instant>,
+}
+
+impl LimiterState {
+    /// Take a token from every bucket if all of them have one; otherwise,
+    /// return how long to wait before trying again
+    fn try_acquire(
+        &mut self,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let paused = self
+            .paused_until
+            .map(|until| until.saturating_duration_since(now))
+            .unwrap_or(Duration::ZERO);
+        let wait = self
+            .buckets
+            .iter_mut()
+            .map(|b| b.wait_time(now))
+            .fold(paused, Duration::max);
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        self.buckets.iter_mut().for_each(TokenBucket::take);
+        Ok(())
+    }
+}
+
+/// Wraps another `EmailProvider`, so that sends never exceed the configured
+/// rates (across all callers sharing it), and stop altogether for as long as
+/// the provider asks us to (see `SendError::RateLimited`), or rejects our
+/// requests outright (see `SendError::PermanentConfig`).
+///
+/// Sends over the limit are not rejected; `send_email` just waits until a
+/// token is available.
+pub struct RateLimitedClient {
+    inner: Arc<dyn EmailProvider>,
+    // only held for (non-async) bookkeeping, never across an `.await`
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimitedClient {
+    /// Unlimited if neither `max_per_second` nor `max_per_hour` is set, but
+    /// `SendError::RateLimited` responses are honoured regardless
+    pub fn new(
+        inner: Arc<dyn EmailProvider>,
+        max_per_second: Option<NonZeroU32>,
+        max_per_hour: Option<NonZeroU32>,
+    ) -> Self {
+        let now = Instant::now();
+        let buckets = [
+            max_per_second.map(|n| TokenBucket::new(n, Duration::from_secs(1), now)),
+            max_per_hour.map(|n| TokenBucket::new(n, Duration::from_secs(3600), now)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        Self {
+            inner,
+            state: Mutex::new(LimiterState {
+                buckets,
+                paused_until: None,
+            }),
+        }
+    }
+
+    async fn acquire(&self) {
+        loop {
+            let wait = match self.state.lock().unwrap().try_acquire(Instant::now()) {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            tracing::debug!("rate limited, waiting {} ms", wait.as_millis());
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn pause(
+        &self,
+        duration: Duration,
+    ) {
+        let until = Instant::now() + duration;
+        let mut state = self.state.lock().unwrap();
+        // concurrent sends may all be rate limited; keep the longest pause
+        state.paused_until = state.paused_until.max(Some(until));
+    }
+}
+
+#[async_trait]
+impl EmailProvider for RateLimitedClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Option<String>, SendError> {
+        self.acquire().await;
+        let result = self
+            .inner
+            .send_email(recipient, subject, html_content, text_content, headers)
+            .await;
+        match &result {
+            Err(SendError::RateLimited { retry_after }) => {
+                let pause = retry_after.unwrap_or(SendError::DEFAULT_PAUSE);
+                tracing::warn!("provider rate limit hit, pausing for {:?}", pause);
+                self.pause(pause);
+            }
+            // every other send would be rejected too
+            Err(SendError::PermanentConfig(_)) => {
+                tracing::error!(
+                    "request rejected, check email_client settings; pausing for {:?}",
+                    SendError::CONFIG_PAUSE
+                );
+                self.pause(SendError::CONFIG_PAUSE);
+            }
+            _ => {}
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use async_trait::async_trait;
+    use claims::assert_err;
+    use claims::assert_ok;
+
+    use super::parse_retry_after;
+    use super::LimiterState;
+    use super::RateLimitedClient;
+    use super::TokenBucket;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailProvider;
+    use crate::email_client::SendError;
+
+    fn n(n: u32) -> NonZeroU32 { NonZeroU32::new(n).unwrap() }
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(n(2), Duration::from_secs(1), start);
+
+        for _ in 0..2 {
+            assert_eq!(bucket.wait_time(start), Duration::ZERO);
+            bucket.take();
+        }
+        assert_eq!(bucket.wait_time(start), Duration::from_millis(500));
+
+        // one token every 500 ms, never more than `capacity`
+        assert_eq!(
+            bucket.wait_time(start + Duration::from_millis(500)),
+            Duration::ZERO
+        );
+        bucket.refill(start + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn slowest_bucket_wins() {
+        let start = Instant::now();
+        let mut state = LimiterState {
+            buckets: vec![
+                TokenBucket::new(n(10), Duration::from_secs(1), start),
+                TokenBucket::new(n(1), Duration::from_secs(3600), start),
+            ],
+            paused_until: None,
+        };
+        assert_ok!(state.try_acquire(start));
+        let wait = state.try_acquire(start).unwrap_err();
+        assert!(wait > Duration::from_secs(3599));
+    }
+
+    #[test]
+    fn pause_blocks_until_it_expires() {
+        let start = Instant::now();
+        let mut state = LimiterState {
+            buckets: vec![],
+            paused_until: Some(start + Duration::from_secs(5)),
+        };
+        assert_eq!(state.try_acquire(start), Err(Duration::from_secs(5)));
+        assert_ok!(state.try_acquire(start + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn retry_after_seconds_and_dates() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        // in the past
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    /// Rate limited on the first send only
+    struct Throttled {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl EmailProvider for Throttled {
+        async fn send_email(
+            &self,
+            _: &SubscriberEmail,
+            _: &str,
+            _: &str,
+            _: &str,
+            _: &[(&str, &str)],
+        ) -> Result<Option<String>, SendError> {
+            match self.calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(SendError::RateLimited {
+                    retry_after: Some(Duration::from_millis(300)),
+                }),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_response_pauses_later_sends() {
+        let client = RateLimitedClient::new(
+            Arc::new(Throttled {
+                calls: AtomicUsize::new(0),
+            }),
+            None,
+            None,
+        );
+        let email = SubscriberEmail::parse("foo@bar.com".to_string()).unwrap();
+
+        let e = client
+            .send_email(&email, "", "", "", &[])
+            .await
+            .unwrap_err();
+        assert!(matches!(e, SendError::RateLimited { .. }));
+
+        let start = Instant::now();
+        assert_ok!(client.send_email(&email, "", "", "", &[]).await);
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn failures_are_passed_through() {
+        struct Broken;
+        #[async_trait]
+        impl EmailProvider for Broken {
+            async fn send_email(
+                &self,
+                _: &SubscriberEmail,
+                _: &str,
+                _: &str,
+                _: &str,
+                _: &[(&str, &str)],
+            ) -> Result<Option<String>, SendError> {
+                Err(SendError::Transient(anyhow::anyhow!("boom")))
+            }
+        }
+
+        let client = RateLimitedClient::new(Arc::new(Broken), Some(n(1)), None);
+        let email = SubscriberEmail::parse("foo@bar.com".to_string()).unwrap();
+        assert_err!(client.send_email(&email, "", "", "", &[]).await);
+        // not paused, only limited by the bucket
+        assert_eq!(client.state.lock().unwrap().paused_until, None);
+    }
+
+    #[tokio::test]
+    async fn config_error_pauses_later_sends() {
+        struct Misconfigured;
+        #[async_trait]
+        impl EmailProvider for Misconfigured {
+            async fn send_email(
+                &self,
+                _: &SubscriberEmail,
+                _: &str,
+                _: &str,
+                _: &str,
+                _: &[(&str, &str)],
+            ) -> Result<Option<String>, SendError> {
+                Err(SendError::PermanentConfig(anyhow::anyhow!("bad token")))
+            }
+        }
+
+        let client = RateLimitedClient::new(Arc::new(Misconfigured), None, None);
+        let email = SubscriberEmail::parse("foo@bar.com".to_string()).unwrap();
+        let start = Instant::now();
+        assert_err!(client.send_email(&email, "", "", "", &[]).await);
+        let paused_until = client.state.lock().unwrap().paused_until.unwrap();
+        assert!(paused_until >= start + SendError::CONFIG_PAUSE);
+    }
+}
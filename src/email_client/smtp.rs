@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::authentication::Mechanism;
+use lettre::transport::smtp::client::Tls;
+use lettre::transport::smtp::client::TlsParameters;
+use lettre::transport::smtp::PoolConfig;
+use lettre::AsyncSmtpTransport;
+use lettre::AsyncTransport;
+use lettre::Tokio1Executor;
+use secrecy::ExposeSecret;
+
+use super::mime::message_id;
+use super::mime::mime_message;
+use super::EmailProvider;
+use super::SendError;
+use crate::configuration::SmtpAuthMechanism;
+use crate::configuration::SmtpSettings;
+use crate::configuration::SmtpTls;
+use crate::domain::SubscriberEmail;
+
+/// Sends emails to an SMTP server (e.g. a self-hosted relay), for when no HTTP
+/// API is available.
+///
+/// Like `reqwest::Client`, the transport keeps a pool of open connections, so
+/// consecutive deliveries (i.e. a whole issue) reuse the same connection
+/// instead of going through the handshake, TLS and `AUTH` for every recipient.
+pub struct SmtpClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpClient {
+    /// `username` and `password` are only sent if both are given. No
+    /// connection is made until the first email is sent.
+    pub fn new(
+        settings: &SmtpSettings,
+        sender: SubscriberEmail,
+        timeout: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let tls = match settings.tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::Starttls => Tls::Required(
+                TlsParameters::new(settings.host.clone()).context("Invalid SMTP host")?,
+            ),
+            SmtpTls::Tls => Tls::Wrapper(
+                TlsParameters::new(settings.host.clone()).context("Invalid SMTP host")?,
+            ),
+        };
+
+        let mechanisms = settings
+            .auth_mechanisms
+            .iter()
+            .map(|m| match m {
+                SmtpAuthMechanism::Plain => Mechanism::Plain,
+                SmtpAuthMechanism::Login => Mechanism::Login,
+            })
+            .collect();
+
+        let pool = PoolConfig::new()
+            .max_size(settings.max_connections)
+            .idle_timeout(Duration::from_secs(settings.idle_timeout_secs));
+
+        // `builder_dangerous` only means that `tls` is not set for us
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            .port(settings.port)
+            .tls(tls)
+            .authentication(mechanisms)
+            .timeout(Some(timeout))
+            .pool_config(pool);
+        if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                password.expose_secret().to_string(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait]
+impl EmailProvider for SmtpClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Option<String>, SendError> {
+        let message = mime_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+        let message_id = message_id(&message);
+        // the connection is returned to the pool once the response is read
+        self.transport.send(message).await.map_err(classify_error)?;
+        Ok(message_id)
+    }
+}
+
+/// `5yz` replies that are about the recipient's mailbox (RFC 5321): 550
+/// mailbox unavailable, 551 user not local, 552 mailbox full, 553 mailbox name
+/// not allowed
+const RECIPIENT_CODES: [u16; 4] = [550, 551, 552, 553];
+
+/// `4yz` replies (and anything that isn't a reply, e.g. a dropped connection)
+/// are transient; `5yz` replies are permanent, and blamed on the recipient
+/// only for the mailbox codes above
+fn classify_error(e: lettre::transport::smtp::Error) -> SendError {
+    match e.status().map(u16::from) {
+        Some(code) if e.is_permanent() && RECIPIENT_CODES.contains(&code) => {
+            SendError::PermanentRecipient(e.to_string())
+        }
+        Some(_) if e.is_permanent() => SendError::PermanentConfig(e.into()),
+        _ => SendError::Transient(e.into()),
+    }
+}
@@ -10,6 +10,7 @@ use std::time::Duration;
 use sqlx::PgPool;
 
 use crate::configuration::Settings;
+use crate::shutdown::Shutdown;
 use crate::startup::get_connection_pool;
 
 async fn expire_old_keys(pool: &PgPool) -> Result<(), anyhow::Error> {
@@ -25,17 +26,25 @@ async fn expire_old_keys(pool: &PgPool) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-async fn expire_keys_loop(pool: &PgPool) -> Result<(), anyhow::Error> {
-    loop {
+async fn expire_keys_loop(
+    pool: &PgPool,
+    shutdown: &Shutdown,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_triggered() {
         match expire_old_keys(pool).await {
-            Err(_) => tokio::time::sleep(Duration::from_secs(60)).await,
-            Ok(_) => tokio::time::sleep(Duration::from_secs(600)).await,
+            Err(_) => shutdown.sleep(Duration::from_secs(60)).await,
+            Ok(_) => shutdown.sleep(Duration::from_secs(600)).await,
         }
     }
+    Ok(())
 }
 
-/// To be run as a separate worker, outside the main API
-pub async fn init_expiry_worker(cfg: Settings) -> Result<(), anyhow::Error> {
+/// To be run as a separate worker, outside the main API. Returns once
+/// `shutdown` is triggered.
+pub async fn init_expiry_worker(
+    cfg: Settings,
+    shutdown: Shutdown,
+) -> Result<(), anyhow::Error> {
     let pool = get_connection_pool(&cfg.database);
-    expire_keys_loop(&pool).await
+    expire_keys_loop(&pool, &shutdown).await
 }
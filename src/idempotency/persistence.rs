@@ -1,7 +1,6 @@
 use actix_web::body::to_bytes;
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
-use sqlx::postgres::PgHasArrayType;
 use sqlx::Executor;
 use sqlx::PgPool;
 use sqlx::Postgres;
@@ -10,7 +9,9 @@ use uuid::Uuid;
 
 use super::IdempotencyKey;
 
-// both derive and sqlx are required
+// both derive and sqlx are required. since sqlx 0.8, the derive also
+// implements `PgHasArrayType` (`_header_pair`), so the array type no longer
+// needs to be declared by hand
 #[derive(sqlx::Type, Debug)]
 #[sqlx(type_name = "header_pair")] // tell sqlx about the composite type
 struct HeaderPairRecord {
@@ -18,19 +19,13 @@ struct HeaderPairRecord {
     value: Vec<u8>,
 }
 
-// tell sqlx about the array containing the composite type
-impl PgHasArrayType for HeaderPairRecord {
-    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
-        sqlx::postgres::PgTypeInfo::with_name("_header_pair")
-    }
-}
-
 // In-memory locks (e.g. tokio::sync::Mutex) would work if all incoming requests
 // were being served by a single API instance. This is not our case: our API is
 // replicated, therefore the two requests might end up being processed by two
 // different instances. Our synchronization mechanism will have to live
 // out-of-process - our database being the natural candidate.
 /// Used to achieve concurrency on a database level
+#[allow(clippy::large_enum_variant)] // only ever constructed once per request
 pub enum NextAction {
     // StartProcessing,
     StartProcessing(Transaction<'static, Postgres>),
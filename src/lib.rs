@@ -4,8 +4,13 @@ pub mod delivery;
 pub mod domain;
 pub mod email_client;
 pub mod idempotency;
+pub mod outbox;
 pub mod routes;
+pub mod scheduler;
 pub mod session_state;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
+pub mod templates;
+pub mod token_cleanup;
 pub mod utils;
@@ -1,22 +1,41 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fmt::Display;
 
+use tokio::task::Id;
 use tokio::task::JoinError;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 use zero_to_prod::configuration::get_configuration;
 use zero_to_prod::delivery::init_delivery_worker;
 use zero_to_prod::idempotency::init_expiry_worker;
+use zero_to_prod::scheduler::init_scheduler_worker;
+use zero_to_prod::shutdown;
+use zero_to_prod::shutdown::wait_for_signal;
+use zero_to_prod::startup::get_connection_pool;
 use zero_to_prod::startup::Application;
 use zero_to_prod::telemetry::get_subscriber;
 use zero_to_prod::telemetry::init_subscriber;
+use zero_to_prod::token_cleanup::init_token_cleanup_worker;
+
+/// How a component ended, see `report_exit`
+#[derive(PartialEq)]
+enum Exit {
+    Graceful,
+    Failed,
+    /// Did not stop before the shutdown deadline
+    Aborted,
+}
 
 fn report_exit(
     name: &str,
     // damn, how do you derive this type? beats me...
     outcome: Result<Result<(), impl Debug + Display>, JoinError>,
-) {
+) -> Exit {
     match outcome {
         Ok(Ok(())) => {
-            tracing::info!("{name} exited gracefully")
+            tracing::info!("{name} exited gracefully");
+            Exit::Graceful
         }
 
         Ok(Err(e)) => {
@@ -24,7 +43,13 @@ fn report_exit(
                 error.cause_chain=?e,
                 error.message=%e,
                 "{name} failed (inner)"
-            )
+            );
+            Exit::Failed
+        }
+
+        Err(e) if e.is_cancelled() => {
+            tracing::warn!("{name} aborted");
+            Exit::Aborted
         }
 
         Err(e) => {
@@ -32,11 +57,21 @@ fn report_exit(
                 error.cause_chain=?e,
                 error.message=%e,
                 "{name} failed (outer)"
-            )
+            );
+            Exit::Failed
         }
     }
 }
 
+/// `JoinSet::join_next_with_id` only returns the id of a task that failed as
+/// part of the `JoinError`
+fn split_id<T>(outcome: Result<(Id, T), JoinError>) -> (Id, Result<T, JoinError>) {
+    match outcome {
+        Ok((id, o)) => (id, Ok(o)),
+        Err(e) => (e.id(), Err(e)),
+    }
+}
+
 // note how async must be propagated everywhere;
 // "unless polled, there is no guarantee that [futures] will execute to
 // completion"
@@ -77,26 +112,109 @@ async fn main() -> Result<(), anyhow::Error> {
     // let server = Application::build(cfg).await?;
     // server.run_until_stopped().await?;
 
-    let server = Application::build(cfg.clone()).await?.run_until_stopped();
-    let delivery_worker = init_delivery_worker(cfg.clone());
-    let expiry_worker = init_expiry_worker(cfg);
+    let (trigger, shutdown) = shutdown::channel();
+    let shutdown_timeout = cfg.application.shutdown_timeout();
+    let pool = get_connection_pool(&cfg.database);
+
+    let app = Application::build(cfg.clone()).await?;
+    let server_handle = app.handle();
+    // shared, so that the API and the delivery worker stay within the same limits
+    let email_client = app.email_client();
+    let server = async move { app.run_until_stopped().await.map_err(anyhow::Error::from) };
+    let delivery_worker = init_delivery_worker(cfg.clone(), email_client, shutdown.clone());
+    let scheduler_worker = init_scheduler_worker(cfg.clone(), shutdown.clone());
+    let expiry_worker = init_expiry_worker(cfg.clone(), shutdown.clone());
+    let token_cleanup_worker = init_token_cleanup_worker(cfg, shutdown);
 
     // If `spawn` is not called, all async branches are run on the same thread, and
     // the branches run concurrently, but -not- in parallel. If one branch
     // blocks the thread, -all- other branches will be unable to continue!
 
-    let server_thread = tokio::spawn(server);
-    let delivery_worker_thread = tokio::spawn(delivery_worker);
-    let expiry_worker_thread = tokio::spawn(expiry_worker);
-
-    // Waits on multiple concurrent branches, returning when the **first** branch
-    // completes, cancelling the remaining branches.
+    let mut tasks = JoinSet::new();
+    let mut names = HashMap::new();
+    names.insert(tasks.spawn(server).id(), "API");
+    names.insert(
+        tasks.spawn(delivery_worker).id(),
+        "Background delivery worker",
+    );
+    names.insert(
+        tasks.spawn(scheduler_worker).id(),
+        "Background scheduler worker",
+    );
+    names.insert(tasks.spawn(expiry_worker).id(), "Background expiry worker");
+    names.insert(
+        tasks.spawn(token_cleanup_worker).id(),
+        "Background token cleanup worker",
+    );
+
+    let mut exits = vec![];
+
+    // previously, we returned as soon as the first branch completed, which
+    // killed every other component wherever it happened to be. now, a signal (or
+    // any component exiting) starts a coordinated shutdown of all of them
     tokio::select! {
-        // if let-ish syntax:
-        // result = task => { do_stuff(result) }
-        o = server_thread => { report_exit("API", o) },
-        o = delivery_worker_thread => { report_exit("Background delivery worker", o) },
-        o = expiry_worker_thread => { report_exit("Background expiry worker", o) },
+        signal = wait_for_signal() => {
+            tracing::info!("received {}, shutting down", signal?);
+        }
+        Some(o) = tasks.join_next_with_id() => {
+            let (id, o) = split_id(o);
+            let name = names[&id];
+            exits.push(report_exit(name, o));
+            tracing::warn!("{name} exited early, shutting down");
+        }
+    }
+
+    // stop accepting connections, and tell the workers not to start anything new
+    trigger.trigger();
+    tokio::spawn(server_handle.stop(true));
+
+    let deadline = Instant::now() + shutdown_timeout;
+    loop {
+        match tokio::time::timeout_at(deadline, tasks.join_next_with_id()).await {
+            Ok(Some(o)) => {
+                let (id, o) = split_id(o);
+                exits.push(report_exit(names[&id], o));
+            }
+            Ok(None) => break,
+            Err(_) => {
+                // dropping a transaction rolls it back, so an aborted delivery is
+                // picked up again after the restart
+                tracing::warn!(
+                    "{} components still running after {} s, aborting",
+                    tasks.len(),
+                    shutdown_timeout.as_secs()
+                );
+                tasks.abort_all();
+                break;
+            }
+        }
+    }
+    // aborted tasks stop at their next await, so this doesn't need a timeout
+    while let Some(o) = tasks.join_next_with_id().await {
+        let (id, o) = split_id(o);
+        exits.push(report_exit(names[&id], o));
+    }
+
+    let count = |exit| exits.iter().filter(|e| **e == exit).count();
+    let (graceful, failed, aborted) = (
+        count(Exit::Graceful),
+        count(Exit::Failed),
+        count(Exit::Aborted),
+    );
+    let queued = sqlx::query!("SELECT count(*) AS n FROM issue_delivery_queue")
+        .fetch_one(&pool)
+        .await
+        .map(|r| r.n.unwrap_or(0).to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    tracing::info!(
+        graceful,
+        failed,
+        aborted,
+        "shutdown complete; {queued} deliveries left in the queue"
+    );
+
+    if failed > 0 {
+        anyhow::bail!("{failed} components failed");
     }
 
     // note: the last function call is wrapped by tokio (so LSP can't reach it)
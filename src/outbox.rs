@@ -0,0 +1,191 @@
+// transactional emails, i.e. emails that are triggered by a request (such as
+// confirmation links) rather than published by an admin. the request handler
+// renders the email, and inserts it into `email_outbox` in the same
+// transaction as whatever the email refers to (e.g. a new token), so that the
+// email is sent if and only if the transaction is committed.
+//
+// sending is left to the delivery worker (see `try_send_email`), with the same
+// retries as newsletter issues; a provider outage only delays the email, and
+// no longer fails the request
+
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::Executor;
+use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
+use uuid::Uuid;
+
+use crate::delivery::notify_delivery_worker;
+use crate::delivery::retry_delay;
+use crate::delivery::MAX_RETRIES;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailProvider;
+use crate::email_client::SendError;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/// Enqueue a (rendered) email, and wake up the delivery worker. Nothing is
+/// sent unless `transaction` is committed.
+#[tracing::instrument(skip(transaction, recipient, html_content, text_content))]
+pub async fn enqueue_email(
+    transaction: &mut PgTransaction,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO email_outbox
+            (email_id, recipient, subject, html_content, text_content)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        recipient.as_ref(),
+        subject,
+        html_content,
+        text_content,
+    );
+    transaction.execute(query).await?;
+    notify_delivery_worker(transaction).await?;
+    Ok(())
+}
+
+/// A row in `email_outbox`
+struct OutboxEmail {
+    email_id: Uuid,
+    recipient: String,
+    subject: String,
+    html_content: String,
+    text_content: String,
+    /// Number of failed attempts so far
+    n_retries: i16,
+}
+
+/// Send the next email in `email_outbox` that is due, if any. Returns whether
+/// there was one, i.e. `false` if the outbox is empty (or nothing is due).
+///
+/// Failures are handled like in `try_send_email`, except that there is
+/// nothing to bounce or dead-letter: emails that can't be delivered are
+/// dropped (and logged).
+#[tracing::instrument(skip_all, fields(email_id=tracing::field::Empty), err)]
+pub async fn try_send_outbox_email(
+    pool: &PgPool,
+    email_client: &dyn EmailProvider,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let email = sqlx::query_as!(
+        OutboxEmail,
+        r#"
+        SELECT email_id, recipient, subject, html_content, text_content, n_retries
+        FROM email_outbox
+        WHERE execute_after <= now()
+        ORDER BY enqueued_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(email) = email else {
+        return Ok(false);
+    };
+    tracing::Span::current().record("email_id", tracing::field::display(email.email_id));
+
+    // validated when enqueued, so this should only fail if the row was
+    // inserted by other means
+    let recipient = match SubscriberEmail::parse(email.recipient.clone()) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::warn!("dropping email to invalid address: {e}");
+            remove(transaction, email.email_id).await?;
+            return Ok(true);
+        }
+    };
+
+    let sent = email_client
+        .send_email(
+            &recipient,
+            &email.subject,
+            &email.html_content,
+            &email.text_content,
+            &[],
+        )
+        .await;
+    match sent {
+        Ok(_) => remove(transaction, email.email_id).await?,
+        // not counted as a failure, see `try_send_email`
+        Err(SendError::RateLimited { retry_after }) => {
+            let pause = retry_after.unwrap_or(SendError::DEFAULT_PAUSE);
+            reschedule(transaction, &email, email.n_retries, pause).await?;
+        }
+        Err(SendError::PermanentRecipient(reason)) => {
+            tracing::warn!("recipient rejected, dropping email: {reason}");
+            remove(transaction, email.email_id).await?;
+        }
+        // not counted either, otherwise the email would be dropped before
+        // anybody has had a chance to fix the settings
+        Err(SendError::PermanentConfig(e)) => {
+            tracing::error!(e.cause_chain=?e, "failed to send email");
+            reschedule(
+                transaction,
+                &email,
+                email.n_retries,
+                SendError::CONFIG_PAUSE,
+            )
+            .await?;
+        }
+        Err(e) => {
+            tracing::error!(e.cause_chain=?e, "failed to send email");
+            let retries = email.n_retries + 1;
+            if retries > MAX_RETRIES {
+                tracing::error!("dropping email after {retries} retries!");
+                remove(transaction, email.email_id).await?;
+            } else {
+                reschedule(transaction, &email, retries, retry_delay(retries)).await?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Postpone the next attempt by `delay`. This is the last action in the
+/// transaction.
+async fn reschedule(
+    mut transaction: PgTransaction,
+    email: &OutboxEmail,
+    n_retries: i16,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    tracing::info!("retrying in {} seconds", delay.as_secs());
+    let query = sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET
+            n_retries = $1,
+            execute_after = $2
+        WHERE email_id = $3
+        "#,
+        n_retries,
+        Utc::now() + delay,
+        email.email_id,
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Remove the email from the outbox, whether it was sent or not. This is the
+/// last action in the transaction.
+async fn remove(
+    mut transaction: PgTransaction,
+    email_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!("DELETE FROM email_outbox WHERE email_id = $1", email_id);
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::DateTime;
+use chrono::Utc;
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::templates::flash_contents;
+use crate::templates::Templates;
+use crate::utils::error_500;
+
+struct FailedDelivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_attempts: i16,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+async fn get_failed_deliveries(pool: &PgPool) -> Result<Vec<FailedDelivery>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT
+            f.newsletter_issue_id,
+            i.title,
+            f.subscriber_email,
+            f.n_attempts,
+            f.last_error,
+            f.failed_at
+        FROM failed_deliveries f
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        ORDER BY f.failed_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to get failed deliveries")?;
+    Ok(rows)
+}
+
+/// `GET /admin/deliveries/failed`
+///
+/// Lists deliveries that were given up by the delivery worker, each of which
+/// can be retried or discarded.
+pub async fn failed_deliveries(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rows = get_failed_deliveries(&pool).await.map_err(error_500)?;
+
+    // emails are parsed before being stored, but titles and errors can contain
+    // anything; all of them are escaped by the template
+    let deliveries: Vec<_> = rows
+        .iter()
+        .map(|row| {
+            json!({
+                "newsletter_issue_id": row.newsletter_issue_id,
+                "title": row.title,
+                "subscriber_email": row.subscriber_email,
+                "n_attempts": row.n_attempts,
+                "last_error": row.last_error,
+                "failed_at": row.failed_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            })
+        })
+        .collect();
+    templates
+        .page(
+            "admin/deliveries/failed.html",
+            &json!({
+                "messages": flash_contents(&flash_messages),
+                "deliveries": deliveries,
+            }),
+        )
+        .map_err(error_500)
+}
@@ -0,0 +1,177 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::Executor;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::delivery::notify_delivery_worker;
+use crate::utils::error_500;
+use crate::utils::redirect;
+
+/// Identifies a row in `failed_deliveries`
+#[derive(Deserialize)]
+pub struct FailedDeliveryFormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+/// `POST /admin/deliveries/failed/retry`
+///
+/// Move a failed delivery back into `issue_delivery_queue`, with its retry
+/// count reset. If the subscriber is no longer confirmed (e.g. unsubscribed),
+/// nothing is requeued, and the failed delivery is kept. If the delivery is
+/// already queued, only the failed delivery is removed.
+#[tracing::instrument(name = "Retrying failed delivery", skip(form, pool))]
+pub async fn retry_failed_delivery(
+    form: web::Form<FailedDeliveryFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to begin transaction")
+        .map_err(error_500)?;
+
+    // locked, so that concurrent retries don't both requeue
+    let failed = sqlx::query!(
+        r#"
+        SELECT 1 AS "exists!"
+        FROM failed_deliveries
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        FOR UPDATE
+        "#,
+        form.newsletter_issue_id,
+        form.subscriber_email,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to get failed delivery")
+    .map_err(error_500)?;
+    if failed.is_none() {
+        FlashMessage::error("No such failed delivery.").send();
+        return Ok(redirect("/admin/deliveries/failed"));
+    }
+
+    let subscriber = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE email = $1 AND status = 'confirmed'",
+        form.subscriber_email,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to get subscriber")
+    .map_err(error_500)?;
+
+    // the failed delivery (and its log entry) are the only record that the
+    // issue never arrived, so they are kept unless the delivery is retried.
+    // dropping `transaction` releases the lock
+    let Some(subscriber) = subscriber else {
+        FlashMessage::error(format!(
+            "{} is no longer confirmed; delivery was not requeued.",
+            form.subscriber_email
+        ))
+        .send();
+        return Ok(redirect("/admin/deliveries/failed"));
+    };
+
+    // the delivery may already be queued again (e.g. by another admin); it
+    // will be logged once it finishes, so the failed delivery is still removed
+    let requeued = transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO issue_delivery_queue
+                (newsletter_issue_id, subscriber_email, subscriber_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            form.newsletter_issue_id,
+            form.subscriber_email,
+            subscriber.id,
+        ))
+        .await
+        .context("Failed to requeue delivery")
+        .map_err(error_500)?
+        .rows_affected();
+
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            DELETE FROM failed_deliveries
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = $2
+            "#,
+            form.newsletter_issue_id,
+            form.subscriber_email,
+        ))
+        .await
+        .context("Failed to delete failed delivery")
+        .map_err(error_500)?;
+
+    // no longer failed; logged again once delivered (or given up)
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            DELETE FROM issue_deliveries
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = $2
+            "#,
+            form.newsletter_issue_id,
+            form.subscriber_email,
+        ))
+        .await
+        .context("Failed to delete delivery log")
+        .map_err(error_500)?;
+
+    notify_delivery_worker(&mut transaction)
+        .await
+        .context("Failed to notify delivery worker")
+        .map_err(error_500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")
+        .map_err(error_500)?;
+
+    let message = if requeued == 0 {
+        "was already queued"
+    } else {
+        "has been requeued"
+    };
+    FlashMessage::info(format!("Delivery to {} {message}.", form.subscriber_email)).send();
+    Ok(redirect("/admin/deliveries/failed"))
+}
+
+/// `POST /admin/deliveries/failed/discard`
+#[tracing::instrument(name = "Discarding failed delivery", skip(form, pool))]
+pub async fn discard_failed_delivery(
+    form: web::Form<FailedDeliveryFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM failed_deliveries
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        form.newsletter_issue_id,
+        form.subscriber_email,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to delete failed delivery")
+    .map_err(error_500)?;
+
+    FlashMessage::info(format!(
+        "Delivery to {} has been discarded.",
+        form.subscriber_email
+    ))
+    .send();
+    Ok(redirect("/admin/deliveries/failed"))
+}
@@ -0,0 +1,274 @@
+use actix_web::web;
+use actix_web::web::Query;
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::templates::Templates;
+use crate::utils::error_404;
+use crate::utils::error_500;
+
+/// Delivery counts of a published issue. Pending deliveries are counted from
+/// `issue_delivery_queue`, finished ones from `issue_deliveries`.
+struct IssueProgress {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: Option<DateTime<Utc>>,
+    /// Not attempted yet
+    queued: i64,
+    /// Failed at least once, waiting for `execute_after`
+    retrying: i64,
+    sent: i64,
+    skipped: i64,
+    /// Rejected by the provider for good; the subscriber is marked as bounced
+    bounced: i64,
+    /// Dead-lettered; see `failed_deliveries`
+    failed: i64,
+}
+
+impl IssueProgress {
+    fn total(&self) -> i64 {
+        self.queued + self.retrying + self.sent + self.skipped + self.bounced + self.failed
+    }
+
+    /// Context for `admin/deliveries/*.html`
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.newsletter_issue_id,
+            "title": self.title,
+            "published_at": self
+                .published_at
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default(),
+            "queued": self.queued,
+            "retrying": self.retrying,
+            "sent": self.sent,
+            "skipped": self.skipped,
+            "bounced": self.bounced,
+            "failed": self.failed,
+            "total": self.total(),
+            "summary": self.summary(),
+        })
+    }
+
+    fn summary(&self) -> String {
+        match self.queued + self.retrying {
+            0 => "done".to_string(),
+            n => format!(
+                "{}% done, {n} pending",
+                100 * (self.total() - n) / self.total()
+            ),
+        }
+    }
+}
+
+/// All published issues (most recent first), or only `issue_id`
+async fn get_progress(
+    pool: &PgPool,
+    issue_id: Option<Uuid>,
+) -> Result<Vec<IssueProgress>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        IssueProgress,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.published_at,
+            (
+                SELECT count(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id AND q.n_retries = 0
+            ) AS "queued!",
+            (
+                SELECT count(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id AND q.n_retries > 0
+            ) AS "retrying!",
+            (
+                SELECT count(*) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id AND d.status = 'sent'
+            ) AS "sent!",
+            (
+                SELECT count(*) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id AND d.status = 'skipped'
+            ) AS "skipped!",
+            (
+                SELECT count(*) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id AND d.status = 'bounced'
+            ) AS "bounced!",
+            (
+                SELECT count(*) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id AND d.status = 'failed'
+            ) AS "failed!"
+        FROM newsletter_issues i
+        WHERE
+            i.status = 'published' AND
+            ($1::uuid IS NULL OR i.newsletter_issue_id = $1)
+        ORDER BY i.published_at DESC
+        "#,
+        issue_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to get delivery progress")?;
+    Ok(rows)
+}
+
+/// `GET /admin/deliveries`
+///
+/// Delivery progress of every published issue
+pub async fn delivery_status(
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rows = get_progress(&pool, None).await.map_err(error_500)?;
+
+    let issues: Vec<_> = rows.iter().map(IssueProgress::to_json).collect();
+    templates
+        .page("admin/deliveries/status.html", &json!({ "issues": issues }))
+        .map_err(error_500)
+}
+
+/// `GET /admin/deliveries/issues/{id}`
+///
+/// Delivery progress of a single issue, including the deliveries that are
+/// being retried
+pub async fn issue_delivery_status(
+    id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let progress = get_progress(&pool, Some(*id))
+        .await
+        .map_err(error_500)?
+        .pop()
+        .ok_or_else(|| error_404("No such issue"))?;
+
+    let retrying = sqlx::query!(
+        r#"
+        SELECT subscriber_email, n_retries, execute_after
+        FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            n_retries > 0
+        ORDER BY execute_after
+        "#,
+        *id,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to get retrying deliveries")
+    .map_err(error_500)?;
+
+    let retrying: Vec<_> = retrying
+        .iter()
+        .map(|row| {
+            json!({
+                "subscriber_email": row.subscriber_email,
+                "n_retries": row.n_retries,
+                "execute_after": row.execute_after.format("%Y-%m-%d %H:%M:%S").to_string(),
+            })
+        })
+        .collect();
+    templates
+        .page(
+            "admin/deliveries/issue.html",
+            &json!({
+                "issue": progress.to_json(),
+                "retrying": retrying,
+            }),
+        )
+        .map_err(error_500)
+}
+
+#[derive(Deserialize)]
+pub struct SubscriberParameters {
+    email: String,
+}
+
+/// Finished (from `issue_deliveries`) or pending (from `issue_delivery_queue`)
+/// delivery to a single address
+struct SubscriberDelivery {
+    title: String,
+    status: String,
+    n_attempts: i16,
+    provider_message_id: Option<String>,
+    enqueued_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+/// `GET /admin/deliveries/subscriber?email=...`
+///
+/// Every delivery to `email`, most recent first
+pub async fn subscriber_deliveries(
+    params: Query<SubscriberParameters>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rows = sqlx::query_as!(
+        SubscriberDelivery,
+        r#"
+        SELECT
+            i.title AS "title!",
+            d.status AS "status!",
+            d.n_attempts AS "n_attempts!",
+            d.provider_message_id,
+            d.enqueued_at AS "enqueued_at!",
+            d.finished_at AS "finished_at?"
+        FROM issue_deliveries d
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE d.subscriber_email = $1
+
+        UNION ALL
+
+        SELECT
+            i.title,
+            CASE WHEN q.n_retries = 0 THEN 'queued' ELSE 'retrying' END,
+            q.n_retries,
+            NULL,
+            q.enqueued_at,
+            NULL
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE q.subscriber_email = $1
+
+        -- i.e. `enqueued_at`, whose alias can't be used here
+        ORDER BY 5 DESC
+        "#,
+        params.email,
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to get deliveries")
+    .map_err(error_500)?;
+
+    let deliveries: Vec<_> = rows
+        .iter()
+        .map(|row| {
+            json!({
+                "title": row.title,
+                "status": row.status,
+                "n_attempts": row.n_attempts,
+                "provider_message_id": row.provider_message_id.as_deref().unwrap_or_default(),
+                "enqueued_at": row.enqueued_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                "finished_at": row
+                    .finished_at
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+            })
+        })
+        .collect();
+    templates
+        .page(
+            "admin/deliveries/subscriber.html",
+            &json!({
+                "email": params.email,
+                "deliveries": deliveries,
+            }),
+        )
+        .map_err(error_500)
+}
@@ -1,6 +1,8 @@
 mod dashboard;
+mod deliveries;
 mod logout;
 mod password;
 pub use dashboard::admin_dashboard;
+pub use deliveries::*;
 pub use logout::logout;
 pub use password::*;
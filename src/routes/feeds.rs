@@ -0,0 +1,375 @@
+use std::time::SystemTime;
+
+use actix_web::http::header;
+use actix_web::http::header::EntityTag;
+use actix_web::http::header::Header;
+use actix_web::http::header::HttpDate;
+use actix_web::http::header::IfModifiedSince;
+use actix_web::http::header::IfNoneMatch;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::DateTime;
+use chrono::SubsecRound;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::public_content;
+use crate::domain::NewsletterContent;
+use crate::startup::AppBaseUrl;
+use crate::utils::error_500;
+
+// both feeds are built by hand; they only contain a handful of elements, and
+// everything interpolated goes through `escape`. issues cannot be modified once
+// published, so the number of published issues and the latest `published_at`
+// are enough to tell whether a feed has changed
+
+/// Number of issues in each feed
+const FEED_SIZE: i64 = 20;
+
+const FEED_TITLE: &str = "Newsletter";
+
+/// Escape text for use in XML elements and (double-quoted) attributes
+fn escape(s: &str) -> String { htmlescape::encode_minimal(s) }
+
+/// Summary of all published issues, for conditional requests
+struct FeedVersion {
+    n_issues: i64,
+    latest: Option<DateTime<Utc>>,
+}
+
+impl FeedVersion {
+    fn etag(&self) -> EntityTag {
+        let latest = self.latest.map_or(0, |t| t.timestamp_micros());
+        EntityTag::new_strong(format!("{}-{latest}", self.n_issues))
+    }
+
+    /// Truncated to seconds, like `If-Modified-Since`
+    fn last_modified(&self) -> Option<HttpDate> {
+        self.latest
+            .map(|t| HttpDate::from(SystemTime::from(t.trunc_subsecs(0))))
+    }
+
+    /// `If-None-Match` takes precedence over `If-Modified-Since` (RFC 9110)
+    fn is_fresh(
+        &self,
+        req: &HttpRequest,
+    ) -> bool {
+        // a missing header would parse as an empty list, which never matches
+        if req.headers().contains_key(header::IF_NONE_MATCH) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(&self.etag())),
+                Err(_) => false,
+            };
+        }
+        match (IfModifiedSince::parse(req), self.last_modified()) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        }
+    }
+
+    /// Either `304 Not Modified` (without body) or `200 OK`, with `ETag` and
+    /// `Last-Modified` set in both cases. `body` is only called for the latter.
+    fn response(
+        &self,
+        req: &HttpRequest,
+        content_type: &str,
+        body: impl FnOnce() -> String,
+    ) -> HttpResponse {
+        let fresh = self.is_fresh(req);
+        let mut resp = match fresh {
+            true => HttpResponse::NotModified(),
+            false => HttpResponse::Ok(),
+        };
+        resp.insert_header(header::ETag(self.etag()));
+        if let Some(last_modified) = self.last_modified() {
+            resp.insert_header(header::LastModified(last_modified));
+        }
+        match fresh {
+            true => resp.finish(),
+            false => resp.content_type(content_type).body(body()),
+        }
+    }
+}
+
+async fn get_feed_version(pool: &PgPool) -> Result<FeedVersion, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT count(*) AS "n_issues!", max(published_at) AS latest
+        FROM newsletter_issues
+        WHERE
+            status = 'published' AND
+            published_at IS NOT NULL
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to get feed version")?;
+    Ok(FeedVersion {
+        n_issues: row.n_issues,
+        latest: row.latest,
+    })
+}
+
+struct FeedEntry {
+    id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+    /// Rendered like `/issues/{id}`
+    content: NewsletterContent,
+}
+
+async fn get_feed_entries(
+    pool: &PgPool,
+    home_url: &str,
+) -> Result<Vec<FeedEntry>, anyhow::Error> {
+    let entries = sqlx::query!(
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            content,
+            html_content,
+            text_content,
+            published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE
+            status = 'published' AND
+            published_at IS NOT NULL
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        FEED_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to get feed entries")?
+    .into_iter()
+    .map(|r| {
+        let fallback = NewsletterContent {
+            html: r.html_content,
+            text: r.text_content,
+        };
+        FeedEntry {
+            id: r.newsletter_issue_id,
+            title: r.title,
+            published_at: r.published_at,
+            content: public_content(&r.content, fallback, home_url, r.published_at),
+        }
+    })
+    .collect();
+    Ok(entries)
+}
+
+/// RFC 4287
+fn atom_feed(
+    base_url: &str,
+    version: &FeedVersion,
+    entries: &[FeedEntry],
+) -> String {
+    let mut entries_xml = String::new();
+    for entry in entries {
+        entries_xml.push_str(&format!(
+            r#"
+  <entry>
+    <id>urn:uuid:{id}</id>
+    <title>{title}</title>
+    <link rel="alternate" type="text/html" href="{link}"/>
+    <published>{published}</published>
+    <updated>{published}</updated>
+    <content type="html">{content}</content>
+  </entry>"#,
+            id = entry.id,
+            title = escape(&entry.title),
+            link = escape(&format!("{base_url}/issues/{}", entry.id)),
+            published = entry.published_at.to_rfc3339(),
+            content = escape(&entry.content.html),
+        ));
+    }
+
+    // `updated` is required, even for an empty feed
+    let updated = version.latest.unwrap_or(DateTime::UNIX_EPOCH).to_rfc3339();
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>{home}</id>
+  <title>{FEED_TITLE}</title>
+  <link rel="alternate" type="text/html" href="{home}"/>
+  <link rel="self" type="application/atom+xml" href="{self_link}"/>
+  <updated>{updated}</updated>
+  <author><name>{FEED_TITLE}</name></author>{entries_xml}
+</feed>
+"#,
+        home = escape(&format!("{base_url}/")),
+        self_link = escape(&format!("{base_url}/feed.xml")),
+    )
+}
+
+/// RSS 2.0
+fn rss_feed(
+    base_url: &str,
+    entries: &[FeedEntry],
+) -> String {
+    let mut items_xml = String::new();
+    for entry in entries {
+        items_xml.push_str(&format!(
+            r#"
+    <item>
+      <guid isPermaLink="false">urn:uuid:{id}</guid>
+      <title>{title}</title>
+      <link>{link}</link>
+      <pubDate>{published}</pubDate>
+      <description>{content}</description>
+    </item>"#,
+            id = entry.id,
+            title = escape(&entry.title),
+            link = escape(&format!("{base_url}/issues/{}", entry.id)),
+            published = entry.published_at.to_rfc2822(),
+            content = escape(&entry.content.html),
+        ));
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+  <channel>
+    <title>{FEED_TITLE}</title>
+    <link>{home}</link>
+    <description>Past issues of our newsletter</description>{items_xml}
+  </channel>
+</rss>
+"#,
+        home = escape(&format!("{base_url}/")),
+    )
+}
+
+/// `GET /feed.xml`
+///
+/// Atom feed of the latest published issues
+pub async fn atom(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<AppBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let version = get_feed_version(&pool).await.map_err(error_500)?;
+    // skip the (larger) query if the client is up to date
+    if version.is_fresh(&req) {
+        return Ok(version.response(&req, "", String::new));
+    }
+    let entries = get_feed_entries(&pool, &format!("{}/", base_url.0))
+        .await
+        .map_err(error_500)?;
+    Ok(
+        version.response(&req, "application/atom+xml; charset=utf-8", || {
+            atom_feed(&base_url.0, &version, &entries)
+        }),
+    )
+}
+
+/// `GET /rss.xml`
+///
+/// Same as `/feed.xml`, for readers that only support RSS
+pub async fn rss(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<AppBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let version = get_feed_version(&pool).await.map_err(error_500)?;
+    // skip the (larger) query if the client is up to date
+    if version.is_fresh(&req) {
+        return Ok(version.response(&req, "", String::new));
+    }
+    let entries = get_feed_entries(&pool, &format!("{}/", base_url.0))
+        .await
+        .map_err(error_500)?;
+    Ok(
+        version.response(&req, "application/rss+xml; charset=utf-8", || {
+            rss_feed(&base_url.0, &entries)
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn version() -> FeedVersion {
+        FeedVersion {
+            n_issues: 2,
+            latest: Some(Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap()),
+        }
+    }
+
+    #[test]
+    fn unconditional_request_is_not_fresh() {
+        let req = TestRequest::default().to_http_request();
+        assert!(!version().is_fresh(&req));
+    }
+
+    #[test]
+    fn etag_takes_precedence() {
+        let v = version();
+        let req = TestRequest::default()
+            .insert_header(("If-None-Match", v.etag().to_string()))
+            .to_http_request();
+        assert!(v.is_fresh(&req));
+
+        // a matching date is ignored if the etag doesn't match
+        let req = TestRequest::default()
+            .insert_header(("If-None-Match", r#""1-0""#))
+            .insert_header(("If-Modified-Since", "Sat, 01 Jun 2024 12:00:00 GMT"))
+            .to_http_request();
+        assert!(!v.is_fresh(&req));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let v = version();
+        for (since, fresh) in [
+            ("Sat, 01 Jun 2024 11:59:59 GMT", false),
+            ("Sat, 01 Jun 2024 12:00:00 GMT", true),
+            ("Sun, 02 Jun 2024 00:00:00 GMT", true),
+        ] {
+            let req = TestRequest::default()
+                .insert_header(("If-Modified-Since", since))
+                .to_http_request();
+            assert_eq!(v.is_fresh(&req), fresh, "{since}");
+        }
+
+        // nothing published, nothing to compare with
+        let v = FeedVersion {
+            n_issues: 0,
+            latest: None,
+        };
+        let req = TestRequest::default()
+            .insert_header(("If-Modified-Since", "Sat, 01 Jun 2024 12:00:00 GMT"))
+            .to_http_request();
+        assert!(!v.is_fresh(&req));
+    }
+
+    #[test]
+    fn entries_are_escaped() {
+        let entry = FeedEntry {
+            id: Uuid::nil(),
+            title: "Fish & <chips>".to_string(),
+            published_at: Utc::now(),
+            content: NewsletterContent::from_markdown("**bold** & co"),
+        };
+        let v = version();
+        for xml in [
+            atom_feed("https://example.com", &v, std::slice::from_ref(&entry)),
+            rss_feed("https://example.com", std::slice::from_ref(&entry)),
+        ] {
+            assert!(xml.contains("Fish &amp; &lt;chips&gt;"));
+            assert!(xml.contains("&lt;strong&gt;bold&lt;/strong&gt; &amp;amp; co"));
+            assert!(!xml.contains("<chips>"));
+            assert!(!xml.contains("<strong>"));
+        }
+    }
+}
@@ -1,11 +1,27 @@
-use actix_web::http::header::ContentType;
+use actix_web::web;
 use actix_web::HttpResponse;
+use serde_json::json;
+use sqlx::PgPool;
 
-/// `GET /home`
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
-        // .finish()
-        // path relative to this file (checked at compile time!)
-        .content_type(ContentType::html())
-        .body(include_str!("./home.html"))
+use super::get_published_issues;
+use super::issue_list;
+use crate::templates::Templates;
+use crate::utils::error_500;
+
+/// Number of issues listed on the home page; the rest are under `/issues`
+const LATEST_ISSUES: i64 = 5;
+
+/// `GET /`
+///
+/// Latest issues, and the subscribe form
+pub async fn home(
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool, LATEST_ISSUES, 0)
+        .await
+        .map_err(error_500)?;
+    templates
+        .page("home.html", &json!({ "issues": issue_list(&issues) }))
+        .map_err(error_500)
 }
@@ -0,0 +1,178 @@
+use actix_web::web;
+use actix_web::web::Query;
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::IssueContext;
+use crate::domain::NewsletterContent;
+use crate::startup::AppBaseUrl;
+use crate::templates::Templates;
+use crate::utils::error_404;
+use crate::utils::error_500;
+
+// only `published` issues are public; drafts, scheduled and cancelled issues
+// are only visible under `/admin`
+
+/// Number of issues per page of `/issues`
+const PAGE_SIZE: i64 = 10;
+
+/// Row of `newsletter_issues`, without content
+pub struct IssueSummary {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+}
+
+/// Most recent first
+pub async fn get_published_issues(
+    pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE
+            status = 'published' AND
+            published_at IS NOT NULL
+        ORDER BY published_at DESC
+        LIMIT $1
+        OFFSET $2
+        "#,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to get published issues")?;
+    Ok(issues)
+}
+
+/// Placeholders are filled in with `IssueContext::public`. Issues that are not
+/// valid templates (which can only have been inserted by other means) fall
+/// back to the content rendered at publish time.
+pub fn public_content(
+    source: &str,
+    fallback: NewsletterContent,
+    home_url: &str,
+    published_at: DateTime<Utc>,
+) -> NewsletterContent {
+    let context = IssueContext::public(home_url.to_string(), published_at);
+    NewsletterContent::from_template(source, &context).unwrap_or(fallback)
+}
+
+/// Context for `issues/_list.html`, i.e. one `<li>` per issue, linking to
+/// `/issues/{id}`
+pub fn issue_list(issues: &[IssueSummary]) -> Vec<Value> {
+    issues
+        .iter()
+        .map(|issue| {
+            json!({
+                "id": issue.newsletter_issue_id,
+                "title": issue.title,
+                "published_at": issue.published_at.format("%Y-%m-%d").to_string(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct PageParameters {
+    /// 1-based; defaults to the first page
+    page: Option<i64>,
+}
+
+/// `GET /issues`
+///
+/// Paged list of published issues, most recent first
+pub async fn issues(
+    params: Query<PageParameters>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = params.page.unwrap_or(1).max(1);
+    // a page this far out can't exist anyway; the offset would overflow
+    let offset = (page - 1)
+        .checked_mul(PAGE_SIZE)
+        .ok_or_else(|| error_404("No such page"))?;
+
+    // fetch one extra row to know whether there is a next page
+    let mut issues = get_published_issues(&pool, PAGE_SIZE + 1, offset)
+        .await
+        .map_err(error_500)?;
+    let has_next = issues.len() as i64 > PAGE_SIZE;
+    issues.truncate(PAGE_SIZE as usize);
+
+    templates
+        .page(
+            "issues/list.html",
+            &json!({
+                "issues": issue_list(&issues),
+                "prev_page": (page > 1).then_some(page - 1),
+                "next_page": has_next.then_some(page + 1),
+            }),
+        )
+        .map_err(error_500)
+}
+
+/// `GET /issues/{id}`
+pub async fn issue(
+    id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<AppBaseUrl>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            title,
+            content,
+            html_content,
+            text_content,
+            published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'published' AND
+            published_at IS NOT NULL
+        "#,
+        *id,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to get issue")
+    .map_err(error_500)?
+    .ok_or_else(|| error_404("No such issue"))?;
+
+    let fallback = NewsletterContent {
+        html: issue.html_content,
+        text: issue.text_content,
+    };
+    let content = public_content(
+        &issue.content,
+        fallback,
+        &format!("{}/", base_url.0),
+        issue.published_at,
+    );
+
+    templates
+        .page(
+            "issues/issue.html",
+            &json!({
+                "title": issue.title,
+                "published_at": issue.published_at.format("%Y-%m-%d").to_string(),
+                // already sanitised
+                "html": content.html,
+            }),
+        )
+        .map_err(error_500)
+}
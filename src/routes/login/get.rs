@@ -1,6 +1,11 @@
-use actix_web::http::header::ContentType;
+use actix_web::web;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
+use serde_json::json;
+
+use crate::templates::flash_contents;
+use crate::templates::Templates;
+use crate::utils::error_500;
 
 // #[derive(Deserialize)]
 // pub struct QueryParams {
@@ -44,7 +49,8 @@ pub async fn login_form(
     // secret: web::Data<HmacSecret>,
     // request: HttpRequest,
     flash_messages: IncomingFlashMessages,
-) -> HttpResponse {
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
     // let error_msg = match query {
     //     // no params, or failed to deserialize, e.g. http://localhost:8000/login?error_msg=foo
     //     None => "".to_owned(),
@@ -85,50 +91,14 @@ pub async fn login_form(
     //     Some(error_msg) => format!("<p><i>{}</i></p>", error_msg.value()),
     // };
 
-    let mut error_msg = String::new();
-    for msg in flash_messages.iter() {
-        //.filter(|m| m.level() == Level::Error) {
-        // the book calls `writeln!(String)`, which is no longer allowed?
-        // writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
-        error_msg.push_str(&format!("<p><i>{}</i></p>\n", msg.content()))
-    }
-
-    let body = format!(
-        r#"
-<!doctype html>
-<html lang="en">
-  <head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
-    <title>Login</title>
-  </head>
-  <body>
-    {error_msg}
-    <!-- trigger `POST` request to `/login` on submit; otherwise, credentials will be put in URL! -->
-    <!-- e.g. http://localhost:8000/login?username=foo&password=bar -->
-    <!-- https://developer.mozilla.org/en-US/docs/Web/HTML/Element/form#action -->
-    <form action="/login" method="post">
-      <!-- https://developer.mozilla.org/en-US/docs/Web/HTML/Element/label -->
-      <label>
-        Username
-        <!-- https://developer.mozilla.org/en-US/docs/Web/HTML/Element/Input -->
-        <input type="text" placeholder="Enter Username" name="username" />
-      </label>
-      <label>
-        Password
-        <input type="password" placeholder="Enter Password" name="password" />
-      </label>
-      <button type="submit">Login</button>
-    </form>
-  </body>
-</html>
-    "#,
-    );
-
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        // clear the cookie from `login`
-        // .cookie(Cookie::build("_flash", "").max_age(Duration::ZERO).finish())
-        .body(body)
+    // flash messages are escaped by the template; previously, they were
+    // interpolated as-is
+    templates
+        .page(
+            "login.html",
+            &json!({ "messages": flash_contents(&flash_messages) }),
+        )
+        .map_err(error_500)
 
     // let mut resp = HttpResponse::Ok()
     //     .content_type(ContentType::html())
@@ -0,0 +1,365 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Executor;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::post::scheduled_flash;
+use super::post::validate_issue;
+use super::preview::preview_page;
+use crate::authentication::UserId;
+use crate::domain::NewsletterContent;
+use crate::idempotency::save_response;
+use crate::idempotency::try_save_response;
+use crate::idempotency::IdempotencyKey;
+use crate::idempotency::NextAction;
+use crate::routes::enqueue_delivery_tasks;
+use crate::templates::flash_contents;
+use crate::templates::Templates;
+use crate::utils::error_400;
+use crate::utils::error_404;
+use crate::utils::error_500;
+use crate::utils::redirect;
+
+// drafts are rows in `newsletter_issues` with status 'draft'; publishing a
+// draft just flips its status (to 'published' or 'scheduled'), after which it
+// can no longer be edited
+
+/// Also accepts the fields of `NewsletterForm`, so that the "Save as draft"
+/// button on the newsletter form can post here
+#[derive(Deserialize)]
+pub struct DraftForm {
+    title: String,
+    /// Markdown source
+    content: String,
+}
+
+struct Draft {
+    newsletter_issue_id: Uuid,
+    title: String,
+    content: String,
+}
+
+async fn get_draft(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT newsletter_issue_id, title, content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'draft'
+        "#,
+        id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to get draft")?;
+    Ok(draft)
+}
+
+/// `GET /admin/newsletters/drafts`
+pub async fn drafts(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title
+        FROM newsletter_issues
+        WHERE status = 'draft'
+        ORDER BY title
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to get drafts")
+    .map_err(error_500)?;
+
+    let drafts: Vec<_> = rows
+        .iter()
+        .map(|row| json!({ "id": row.newsletter_issue_id, "title": row.title }))
+        .collect();
+    templates
+        .page(
+            "newsletters/drafts.html",
+            &json!({
+                "messages": flash_contents(&flash_messages),
+                "drafts": drafts,
+            }),
+        )
+        .map_err(error_500)
+}
+
+/// `POST /admin/newsletters/drafts`
+#[tracing::instrument(name = "Creating draft", skip_all)]
+pub async fn create_draft(
+    form: web::Form<DraftForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = Uuid::new_v4();
+    // rendered forms are only kept up to date so that the columns can remain NOT
+    // NULL; they are rendered again on publish
+    let rendered = NewsletterContent::from_markdown(&form.content);
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+        (
+            newsletter_issue_id,
+            title,
+            content,
+            html_content,
+            text_content,
+            status
+        )
+        VALUES ($1, $2, $3, $4, $5, 'draft')
+        "#,
+        id,
+        form.title,
+        form.content,
+        rendered.html,
+        rendered.text,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to insert draft")
+    .map_err(error_500)?;
+
+    FlashMessage::info("Draft has been saved.").send();
+    Ok(redirect(&format!("/admin/newsletters/drafts/{id}")))
+}
+
+/// `GET /admin/newsletters/drafts/{id}`
+///
+/// Edit, preview, delete, or publish a draft
+pub async fn edit_draft(
+    flash_messages: IncomingFlashMessages,
+    id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = get_draft(&pool, *id)
+        .await
+        .map_err(error_500)?
+        .ok_or_else(|| error_404("No such draft"))?;
+
+    // generated per request, like in `newsletter_form`
+    let key = Uuid::new_v4().to_string();
+
+    templates
+        .page(
+            "newsletters/edit_draft.html",
+            &json!({
+                "messages": flash_contents(&flash_messages),
+                "id": draft.newsletter_issue_id,
+                "title": draft.title,
+                "content": draft.content,
+                "idempotency_key": key,
+            }),
+        )
+        .map_err(error_500)
+}
+
+/// `POST /admin/newsletters/drafts/{id}`
+#[tracing::instrument(name = "Updating draft", skip(form, pool))]
+pub async fn update_draft(
+    id: web::Path<Uuid>,
+    form: web::Form<DraftForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rendered = NewsletterContent::from_markdown(&form.content);
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $1,
+            content = $2,
+            html_content = $3,
+            text_content = $4
+        WHERE
+            newsletter_issue_id = $5 AND
+            status = 'draft'
+        "#,
+        form.title,
+        form.content,
+        rendered.html,
+        rendered.text,
+        *id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to update draft")
+    .map_err(error_500)?
+    .rows_affected();
+
+    if updated == 0 {
+        FlashMessage::error("No such draft.").send();
+        return Ok(redirect("/admin/newsletters/drafts"));
+    }
+    FlashMessage::info("Draft has been saved.").send();
+    Ok(redirect(&format!("/admin/newsletters/drafts/{id}")))
+}
+
+/// `POST /admin/newsletters/drafts/{id}/delete`
+#[tracing::instrument(name = "Deleting draft", skip(pool))]
+pub async fn delete_draft(
+    id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'draft'
+        "#,
+        *id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to delete draft")
+    .map_err(error_500)?
+    .rows_affected();
+
+    match deleted {
+        0 => FlashMessage::error("No such draft."),
+        _ => FlashMessage::info("Draft has been deleted."),
+    }
+    .send();
+    Ok(redirect("/admin/newsletters/drafts"))
+}
+
+/// `GET /admin/newsletters/drafts/{id}/preview`
+///
+/// Same as `preview_newsletter`, but for the saved draft
+pub async fn preview_draft(
+    id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = get_draft(&pool, *id)
+        .await
+        .map_err(error_500)?
+        .ok_or_else(|| error_404("No such draft"))?;
+    preview_page(&templates, &draft.title, &draft.content)
+}
+
+#[derive(Deserialize)]
+pub struct PublishDraftForm {
+    idempotency_key: String,
+    send_at: Option<String>,
+}
+
+/// `POST /admin/newsletters/drafts/{id}/publish`
+///
+/// Like `publish_newsletter`, a repeated request (with the same idempotency
+/// key) returns the saved response. The key is only consumed if the draft is
+/// actually published.
+#[tracing::instrument(name = "Publishing draft", skip(form, pool, user_id), fields(user_id=%&*user_id))]
+pub async fn publish_draft(
+    id: web::Path<Uuid>,
+    form: web::Form<PublishDraftForm>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let key: IdempotencyKey = form.idempotency_key.clone().try_into().map_err(error_400)?;
+    let edit_url = format!("/admin/newsletters/drafts/{id}");
+
+    let mut transaction = match try_save_response(*user_id, &key, &pool)
+        .await
+        .map_err(error_500)?
+    {
+        NextAction::ReturnSavedResponse(saved) => {
+            FlashMessage::info("Issue has already been published.").send();
+            return Ok(saved);
+        }
+        NextAction::StartProcessing(t) => t,
+    };
+
+    // from here on, returning early (i.e. dropping `transaction`) rolls back the
+    // idempotency key
+
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT newsletter_issue_id, title, content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'draft'
+        FOR UPDATE
+        "#,
+        *id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to get draft")
+    .map_err(error_500)?;
+    let Some(draft) = draft else {
+        FlashMessage::error("No such draft.").send();
+        return Ok(redirect("/admin/newsletters/drafts"));
+    };
+
+    let send_at = match validate_issue(&draft.content, form.send_at.as_deref()) {
+        Ok(send_at) => send_at,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(redirect(&edit_url));
+        }
+    };
+
+    let rendered = NewsletterContent::from_markdown(&draft.content);
+    let status = match send_at {
+        Some(_) => "scheduled",
+        None => "published",
+    };
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET
+                html_content = $1,
+                text_content = $2,
+                status = $3,
+                send_at = $4,
+                published_at = CASE WHEN $4::timestamptz IS NULL THEN now() END
+            WHERE newsletter_issue_id = $5
+            "#,
+            rendered.html,
+            rendered.text,
+            status,
+            send_at,
+            draft.newsletter_issue_id,
+        ))
+        .await
+        .context("Failed to publish draft")
+        .map_err(error_500)?;
+
+    // scheduled issues are enqueued later, by `init_scheduler_worker`
+    match send_at {
+        Some(send_at) => scheduled_flash(send_at).send(),
+        None => {
+            enqueue_delivery_tasks(&mut transaction, draft.newsletter_issue_id)
+                .await
+                .context("Could not enqueue delivery tasks")
+                .map_err(error_500)?;
+            FlashMessage::info("New issue is being published...").send();
+        }
+    }
+
+    let resp = redirect("/admin/newsletters/drafts");
+    let resp = save_response(*user_id, &key, resp, transaction)
+        .await
+        .map_err(error_500)?;
+    Ok(resp)
+}
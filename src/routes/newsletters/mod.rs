@@ -1,7 +1,17 @@
+mod drafts;
 mod get;
 mod post;
+mod preview;
+mod scheduled;
+mod send_test;
+mod welcome;
+pub use drafts::*;
 pub use get::*;
 pub use post::*;
+pub use preview::*;
+pub use scheduled::*;
+pub use send_test::*;
+pub use welcome::*;
 
 // Parse headers of a HTTP request. This does not actually validate any user
 // credentials; for that, see `validate_credentials`.
@@ -2,6 +2,8 @@ use actix_web::web;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
+use chrono::DateTime;
+use chrono::Utc;
 use serde::Deserialize;
 use sqlx::Executor;
 use sqlx::PgPool;
@@ -10,10 +12,14 @@ use sqlx::Transaction;
 use uuid::Uuid;
 
 use crate::authentication::UserId;
+use crate::delivery::notify_delivery_worker;
+use crate::domain::IssueContext;
+use crate::domain::NewsletterContent;
 use crate::idempotency::save_response;
 use crate::idempotency::try_save_response;
 use crate::idempotency::IdempotencyKey;
 use crate::idempotency::NextAction;
+use crate::routes::parse_send_at;
 use crate::utils::error_400;
 use crate::utils::error_500;
 use crate::utils::redirect;
@@ -22,17 +28,32 @@ use crate::utils::redirect;
 pub struct NewsletterForm {
     title: String,
     // content: NewsletterContent,
+    /// Markdown source
     content: String,
     idempotency_key: String,
+    /// If set (and not empty), the issue is only published at this time; see
+    /// `parse_send_at`
+    send_at: Option<String>,
 }
 
 impl NewsletterForm {
+    /// The Markdown source is stored as well as its rendered forms, so that the
+    /// issue can be re-rendered later.
+    ///
+    /// If `send_at` is given, the issue is stored as `scheduled` (and left for
+    /// `init_scheduler_worker`), otherwise it is `published` immediately.
     #[tracing::instrument(skip_all)]
     pub async fn insert_issue(
         &self,
         transaction: &mut Transaction<'static, Postgres>,
+        send_at: Option<DateTime<Utc>>,
     ) -> Result<Uuid, anyhow::Error> {
         let id = Uuid::new_v4();
+        let rendered = NewsletterContent::from_markdown(&self.content);
+        let (status, published_at) = match send_at {
+            Some(_) => ("scheduled", None),
+            None => ("published", Some(Utc::now())),
+        };
         let query = sqlx::query!(
             r#"
                 INSERT INTO newsletter_issues
@@ -40,13 +61,22 @@ impl NewsletterForm {
                     newsletter_issue_id,
                     title,
                     content,
+                    html_content,
+                    text_content,
+                    status,
+                    send_at,
                     published_at
                 )
-                VALUES ($1, $2, $3, now())
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
             "#,
             id,
             self.title,
             self.content,
+            rendered.html,
+            rendered.text,
+            status,
+            send_at,
+            published_at,
         );
         transaction.execute(query).await?;
 
@@ -54,32 +84,49 @@ impl NewsletterForm {
     }
 }
 
+/// Check everything that can only go wrong after the issue is published:
+/// placeholders are only filled in by the delivery worker, by which time it is
+/// too late to report errors. Returns the parsed `send_at`.
+pub(super) fn validate_issue(
+    content: &str,
+    send_at: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, String> {
+    NewsletterContent::from_template(content, &IssueContext::example())
+        .map_err(|e| format!("Invalid template: {:#}", anyhow::Error::from(e)))?;
+    parse_send_at(send_at.unwrap_or_default())
+}
+
+pub(super) fn scheduled_flash(send_at: DateTime<Utc>) -> FlashMessage {
+    FlashMessage::info(format!(
+        "Issue has been scheduled for {} (UTC).",
+        send_at.format("%Y-%m-%d %H:%M")
+    ))
+}
+
+/// Enqueue one delivery per confirmed subscriber, and wake up the delivery
+/// worker. Called when an issue is published, either immediately
+/// (`publish_newsletter`) or when it is due (`init_scheduler_worker`).
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+pub async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'static, Postgres>,
     newsletter_issue_id: Uuid,
 ) -> Result<(), anyhow::Error> {
     let query = sqlx::query!(
         r#"
-        -- copy from subscriptions
+        -- copy from subscriptions; pending and unsubscribed rows are skipped
         INSERT INTO issue_delivery_queue
-            (newsletter_issue_id, subscriber_email)
-        SELECT $1, email
+            (newsletter_issue_id, subscriber_email, subscriber_id)
+        SELECT $1, email, id
         FROM subscriptions
         WHERE status = 'confirmed'
     "#,
         newsletter_issue_id
     );
     transaction.execute(query).await?;
+    notify_delivery_worker(transaction).await?;
     Ok(())
 }
 
-// #[derive(Deserialize)]
-// struct NewsletterContent {
-//     html: String,
-//     text: String,
-// }
-
 // struct ConfirmedSubscriber {
 //     // email: String,
 //     email: SubscriberEmail,
@@ -225,6 +272,16 @@ pub async fn publish_newsletter(
     // field
     let key: IdempotencyKey = form.idempotency_key.clone().try_into().map_err(error_400)?;
 
+    // this must be checked before the idempotency key is saved, so that the form
+    // can be fixed and resubmitted
+    let send_at = match validate_issue(&form.content, form.send_at.as_deref()) {
+        Ok(send_at) => send_at,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(redirect("/admin/newsletters"));
+        }
+    };
+
     // // if let Ok(Some(saved)) = get_saved_response(*user_id, &key, &pool).await {
     // if let Some(saved) = get_saved_response(*user_id, &key, &pool)
     //     .await
@@ -260,11 +317,21 @@ pub async fn publish_newsletter(
 
     let issue_id = form
         .0
-        .insert_issue(&mut transaction)
+        .insert_issue(&mut transaction, send_at)
         .await
         .context("Could not insert newsletter issue into db")
         .map_err(error_500)?;
 
+    // scheduled issues are enqueued later, by `init_scheduler_worker`
+    if let Some(send_at) = send_at {
+        scheduled_flash(send_at).send();
+        let resp = redirect("/admin/newsletters");
+        let resp = save_response(*user_id, &key, resp, transaction)
+            .await
+            .map_err(error_500)?;
+        return Ok(resp);
+    }
+
     enqueue_delivery_tasks(&mut transaction, issue_id)
         .await
         .context("Could not enqueue delivery tasks")
@@ -0,0 +1,53 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::domain::IssueContext;
+use crate::domain::NewsletterContent;
+use crate::templates::Templates;
+use crate::utils::error_500;
+
+/// Same fields as `NewsletterForm`; `idempotency_key` is ignored, since
+/// nothing is saved
+#[derive(Deserialize)]
+pub struct PreviewForm {
+    title: String,
+    content: String,
+}
+
+/// `POST /admin/newsletters/preview`
+///
+/// Render the Markdown source as the delivery worker would, without storing
+/// anything.
+pub async fn preview_newsletter(
+    form: web::Form<PreviewForm>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    preview_page(&templates, &form.title, &form.content)
+}
+
+/// Placeholders are filled in with `IssueContext::example`. Both the HTML and
+/// plain text bodies are shown.
+pub(super) fn preview_page(
+    templates: &Templates,
+    title: &str,
+    source: &str,
+) -> Result<HttpResponse, actix_web::Error> {
+    let context = match NewsletterContent::from_template(source, &IssueContext::example()) {
+        Ok(content) => json!({
+            "title": title,
+            // already sanitised
+            "html": content.html,
+            "text": content.text,
+        }),
+        Err(e) => json!({
+            "title": title,
+            "error": format!("{:#}", anyhow::Error::from(e)),
+            "text": "",
+        }),
+    };
+    templates
+        .page("newsletters/preview.html", &context)
+        .map_err(error_500)
+}
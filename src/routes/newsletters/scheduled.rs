@@ -0,0 +1,221 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::templates::flash_contents;
+use crate::templates::Templates;
+use crate::utils::error_500;
+use crate::utils::redirect;
+
+/// Format used by `<input type="datetime-local">`
+const DATETIME_LOCAL: &str = "%Y-%m-%dT%H:%M";
+
+/// Parse the value of a `datetime-local` input, which carries no timezone; it
+/// is taken to be UTC. An empty value means "now", i.e. `None`. Times in the
+/// past are rejected, since they were most likely a typo.
+pub fn parse_send_at(value: &str) -> Result<Option<DateTime<Utc>>, String> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    let send_at = NaiveDateTime::parse_from_str(value.trim(), DATETIME_LOCAL)
+        .map_err(|_| format!("Invalid send time: {value:?}"))?
+        .and_utc();
+    if send_at <= Utc::now() {
+        return Err("Send time must be in the future.".to_string());
+    }
+    Ok(Some(send_at))
+}
+
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    send_at: Option<DateTime<Utc>>,
+}
+
+async fn get_scheduled_issues(pool: &PgPool) -> Result<Vec<ScheduledIssue>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id, title, send_at
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY send_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to get scheduled issues")?;
+    Ok(rows)
+}
+
+/// `GET /admin/newsletters/scheduled`
+///
+/// Lists issues that have not yet been published by `init_scheduler_worker`,
+/// each of which can be rescheduled or cancelled.
+pub async fn scheduled_issues(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rows = get_scheduled_issues(&pool).await.map_err(error_500)?;
+
+    let issues: Vec<_> = rows
+        .iter()
+        .map(|row| {
+            json!({
+                "id": row.newsletter_issue_id,
+                "title": row.title,
+                "send_at": row
+                    .send_at
+                    .map(|t| t.format(DATETIME_LOCAL).to_string())
+                    .unwrap_or_default(),
+            })
+        })
+        .collect();
+    templates
+        .page(
+            "newsletters/scheduled.html",
+            &json!({
+                "messages": flash_contents(&flash_messages),
+                "issues": issues,
+            }),
+        )
+        .map_err(error_500)
+}
+
+#[derive(Deserialize)]
+pub struct RescheduleFormData {
+    newsletter_issue_id: Uuid,
+    send_at: String,
+}
+
+/// `POST /admin/newsletters/scheduled/reschedule`
+///
+/// Only issues that are still `scheduled` are affected; if the issue has been
+/// published in the meantime, nothing happens.
+#[tracing::instrument(name = "Rescheduling issue", skip(form, pool))]
+pub async fn reschedule_issue(
+    form: web::Form<RescheduleFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let send_at = match parse_send_at(&form.send_at) {
+        Ok(Some(send_at)) => send_at,
+        Ok(None) => {
+            FlashMessage::error("Send time is required.").send();
+            return Ok(redirect("/admin/newsletters/scheduled"));
+        }
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(redirect("/admin/newsletters/scheduled"));
+        }
+    };
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET send_at = $1
+        WHERE
+            newsletter_issue_id = $2 AND
+            status = 'scheduled'
+        "#,
+        send_at,
+        form.newsletter_issue_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to reschedule issue")
+    .map_err(error_500)?
+    .rows_affected();
+
+    match updated {
+        0 => FlashMessage::error("Issue is no longer scheduled."),
+        _ => FlashMessage::info(format!(
+            "Issue has been rescheduled for {} (UTC).",
+            send_at.format("%Y-%m-%d %H:%M")
+        )),
+    }
+    .send();
+    Ok(redirect("/admin/newsletters/scheduled"))
+}
+
+#[derive(Deserialize)]
+pub struct CancelFormData {
+    newsletter_issue_id: Uuid,
+}
+
+/// `POST /admin/newsletters/scheduled/cancel`
+///
+/// The issue is kept (as `cancelled`), but will never be published.
+#[tracing::instrument(name = "Cancelling scheduled issue", skip(form, pool))]
+pub async fn cancel_issue(
+    form: web::Form<CancelFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'scheduled'
+        "#,
+        form.newsletter_issue_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to cancel issue")
+    .map_err(error_500)?
+    .rows_affected();
+
+    match updated {
+        0 => FlashMessage::error("Issue is no longer scheduled."),
+        _ => FlashMessage::info("Issue has been cancelled."),
+    }
+    .send();
+    Ok(redirect("/admin/newsletters/scheduled"))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    use claims::assert_err;
+    use claims::assert_none;
+
+    use super::parse_send_at;
+    use super::DATETIME_LOCAL;
+
+    #[test]
+    fn empty_send_at_is_now() {
+        assert_none!(parse_send_at("").unwrap());
+        assert_none!(parse_send_at("  ").unwrap());
+    }
+
+    #[test]
+    fn future_send_at_is_ok() {
+        let tomorrow = (Utc::now() + Duration::days(1))
+            .format(DATETIME_LOCAL)
+            .to_string();
+        let send_at = parse_send_at(&tomorrow).unwrap().unwrap();
+        assert_eq!(send_at.format(DATETIME_LOCAL).to_string(), tomorrow);
+    }
+
+    #[test]
+    fn invalid_send_at_is_rejected() {
+        let yesterday = (Utc::now() - Duration::days(1))
+            .format(DATETIME_LOCAL)
+            .to_string();
+        for value in ["foo", "2024-13-01T00:00", &yesterday] {
+            assert_err!(parse_send_at(value), "{value}");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::delivery::send_issue;
+use crate::delivery::SendIssueError;
+use crate::domain::IssueContext;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailProvider;
+use crate::routes::unsubscribe_link;
+use crate::startup::AppBaseUrl;
+use crate::startup::HmacSecret;
+use crate::templates::Templates;
+use crate::utils::error_500;
+use crate::utils::redirect;
+
+// test issues bypass `issue_delivery_queue` entirely: they are sent inline, to
+// a single address, and nothing is stored. in particular, no idempotency key
+// is consumed, so the issue can still be published from the same form
+
+/// Returns the placeholder values that `try_send_email` would use for
+/// `email`. Addresses that don't belong to a subscriber get
+/// `IssueContext::example`, i.e. their unsubscribe link doesn't work.
+async fn test_context(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    base_url: &str,
+    secret: &HmacSecret,
+) -> Result<IssueContext, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id, name, subscribed_at
+        FROM subscriptions
+        WHERE email = $1
+        "#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to get subscriber")?;
+
+    let context = match subscriber {
+        Some(sub) => IssueContext::new(
+            sub.name,
+            unsubscribe_link(base_url, sub.id, secret),
+            sub.subscribed_at,
+        ),
+        None => IssueContext::example(),
+    };
+    Ok(context)
+}
+
+/// Returns a message for the admin; only unexpected (i.e. db) errors are
+/// propagated
+#[tracing::instrument(skip(pool, email_client, source, base_url, secret))]
+async fn send_test(
+    pool: &PgPool,
+    email_client: &dyn EmailProvider,
+    base_url: &str,
+    secret: &HmacSecret,
+    test_email: String,
+    title: &str,
+    source: &str,
+) -> Result<Result<String, String>, anyhow::Error> {
+    let email = match SubscriberEmail::parse(test_email) {
+        Ok(email) => email,
+        Err(e) => return Ok(Err(e)),
+    };
+    let context = test_context(pool, &email, base_url, secret).await?;
+
+    let msg = match send_issue(email_client, &email, title, source, &context).await {
+        Ok(_) => Ok(format!("Test issue has been sent to {}.", email.as_ref())),
+        Err(SendIssueError::RenderError(e)) => {
+            Err(format!("Invalid template: {:#}", anyhow::Error::from(e)))
+        }
+        Err(SendIssueError::SendError(e)) => {
+            tracing::warn!(e.cause_chain=?e, "failed to send test issue");
+            Err(format!("Failed to send test issue: {e}"))
+        }
+    };
+    Ok(msg)
+}
+
+#[derive(Deserialize)]
+pub struct TestIssueForm {
+    title: String,
+    /// Markdown source
+    content: String,
+    test_email: String,
+}
+
+/// `POST /admin/newsletters/test`
+///
+/// Posted from `newsletter_form` (in a new tab, like `preview_newsletter`), so
+/// the result is shown on its own page rather than flashed.
+pub async fn send_test_issue(
+    form: web::Form<TestIssueForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailProvider>,
+    base_url: web::Data<AppBaseUrl>,
+    secret: web::Data<HmacSecret>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.into_inner();
+    let msg = send_test(
+        &pool,
+        email_client.as_ref(),
+        &base_url.0,
+        &secret,
+        form.test_email,
+        &form.title,
+        &form.content,
+    )
+    .await
+    .map_err(error_500)?
+    .unwrap_or_else(|e| e);
+
+    templates
+        .page("newsletters/test.html", &json!({ "result": msg }))
+        .map_err(error_500)
+}
+
+#[derive(Deserialize)]
+pub struct TestDraftForm {
+    test_email: String,
+}
+
+/// `POST /admin/newsletters/drafts/{id}/test`
+///
+/// Sends the last saved version of the draft
+pub async fn send_test_draft(
+    id: web::Path<Uuid>,
+    form: web::Form<TestDraftForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailProvider>,
+    base_url: web::Data<AppBaseUrl>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = sqlx::query!(
+        r#"
+        SELECT title, content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'draft'
+        "#,
+        *id,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to get draft")
+    .map_err(error_500)?;
+    let Some(draft) = draft else {
+        FlashMessage::error("No such draft.").send();
+        return Ok(redirect("/admin/newsletters/drafts"));
+    };
+
+    match send_test(
+        &pool,
+        email_client.as_ref(),
+        &base_url.0,
+        &secret,
+        form.into_inner().test_email,
+        &draft.title,
+        &draft.content,
+    )
+    .await
+    .map_err(error_500)?
+    {
+        Ok(msg) => FlashMessage::info(msg),
+        Err(msg) => FlashMessage::error(msg),
+    }
+    .send();
+    Ok(redirect(&format!("/admin/newsletters/drafts/{id}")))
+}
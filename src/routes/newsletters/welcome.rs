@@ -0,0 +1,142 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
+use uuid::Uuid;
+
+use super::post::validate_issue;
+use crate::delivery::render_issue;
+use crate::domain::IssueContext;
+use crate::domain::SubscriberEmail;
+use crate::outbox::enqueue_email;
+use crate::routes::unsubscribe_link;
+use crate::startup::HmacSecret;
+use crate::templates::flash_contents;
+use crate::templates::Templates;
+use crate::utils::error_500;
+use crate::utils::redirect;
+
+// the welcome email is the single row of `welcome_email` (see the migration).
+// it is rendered for the subscriber when they confirm, and sent through
+// `email_outbox` like the confirmation email, so confirming a subscription
+// never waits for the email provider
+
+/// Enqueue the welcome email for a subscriber who has just confirmed. Does
+/// nothing if the welcome email is disabled, or was already enqueued for the
+/// subscriber.
+#[tracing::instrument(skip(transaction, base_url, secret))]
+pub async fn enqueue_welcome_email(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    base_url: &str,
+    secret: &HmacSecret,
+) -> Result<(), anyhow::Error> {
+    // marking the subscriber in the same statement means that confirming again
+    // (with a new token) doesn't send it twice
+    let Some(welcome) = sqlx::query!(
+        r#"
+        UPDATE subscriptions s
+        SET welcomed_at = now()
+        FROM welcome_email w
+        WHERE
+            w.enabled AND
+            s.id = $1 AND
+            s.welcomed_at IS NULL
+        RETURNING s.email, s.name, s.subscribed_at, w.subject, w.content
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let recipient = SubscriberEmail::parse(welcome.email).map_err(anyhow::Error::msg)?;
+    let link = unsubscribe_link(base_url, subscriber_id, secret);
+    let context = IssueContext::new(welcome.name, link, welcome.subscribed_at);
+    // validated when saved, see `update_welcome_email`
+    let content =
+        render_issue(&welcome.content, &context).context("Failed to render welcome email")?;
+
+    enqueue_email(
+        transaction,
+        &recipient,
+        &welcome.subject,
+        &content.html,
+        &content.text,
+    )
+    .await?;
+    Ok(())
+}
+
+/// `GET /admin/welcome`
+pub async fn welcome_email_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let welcome = sqlx::query!("SELECT subject, content, enabled FROM welcome_email")
+        .fetch_one(pool.as_ref())
+        .await
+        .context("Failed to get welcome email")
+        .map_err(error_500)?;
+
+    templates
+        .page(
+            "newsletters/welcome.html",
+            &json!({
+                "messages": flash_contents(&flash_messages),
+                "title": welcome.subject,
+                "content": welcome.content,
+                "enabled": welcome.enabled,
+            }),
+        )
+        .map_err(error_500)
+}
+
+#[derive(Deserialize)]
+pub struct WelcomeEmailForm {
+    title: String,
+    /// Markdown source
+    content: String,
+    /// Checkbox; absent when unchecked
+    enabled: Option<String>,
+}
+
+/// `POST /admin/welcome`
+///
+/// Only affects subscribers who confirm from now on; welcome emails that are
+/// already queued are sent as they were rendered
+#[tracing::instrument(name = "Updating welcome email", skip_all)]
+pub async fn update_welcome_email(
+    form: web::Form<WelcomeEmailForm>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // nobody sees the email before it is sent, so check it here, like
+    // `publish_newsletter` does
+    if let Err(e) = validate_issue(&form.content, None) {
+        FlashMessage::error(e).send();
+        return Ok(redirect("/admin/welcome"));
+    }
+
+    sqlx::query!(
+        "UPDATE welcome_email SET subject = $1, content = $2, enabled = $3",
+        form.title,
+        form.content,
+        form.enabled.is_some(),
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to update welcome email")
+    .map_err(error_500)?;
+
+    FlashMessage::info("Welcome email has been saved.").send();
+    Ok(redirect("/admin/welcome"))
+}
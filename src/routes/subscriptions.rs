@@ -5,6 +5,7 @@ use actix_web::web;
 use actix_web::HttpResponse;
 use actix_web::ResponseError;
 use anyhow::Context;
+use chrono::DateTime;
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::thread_rng;
@@ -19,8 +20,13 @@ use uuid::Uuid;
 use crate::domain::NewSubscriber;
 use crate::domain::SubscriberEmail;
 use crate::domain::SubscriberName;
-use crate::email_client::EmailClient;
+use crate::outbox::enqueue_email;
 use crate::startup::AppBaseUrl;
+use crate::startup::ConfirmationTokenTtl;
+use crate::templates::Templates;
+
+/// Also used as the `<title>` of the HTML body
+const CONFIRMATION_SUBJECT: &str = "Confirm your subscription";
 
 #[derive(Deserialize)]
 pub struct SubscriberFormData {
@@ -61,64 +67,48 @@ impl TryFrom<SubscriberFormData> for NewSubscriber {
 // after email validation, it is still necessary to confirm user consent with a
 // confirmation email
 
-/// Wrapper for `EmailClient.send_email`. Probably should be declared here and
-/// left private (rather than a public `EmailClient.send_confirmation_email`
-/// method).
+/// Render the confirmation email, and add it to the outbox (see
+/// `enqueue_email`), in the same transaction as the token it contains. It is
+/// sent by the delivery worker once `transaction` is committed.
 #[tracing::instrument(
-    name = "Sending confirmation email to new subscriber",
-    skip(email_client, new_sub, base_url, token)
+    name = "Enqueueing confirmation email to new subscriber",
+    skip(transaction, templates, new_sub, base_url, token)
 )]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'static, Postgres>,
+    templates: &Templates,
     new_sub: NewSubscriber,
     base_url: &str,
     token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirm_link = format!("{base_url}/subscriptions/confirm?subscription_token={token}");
-    println!("sending email to {:?}", new_sub.email);
 
     // https://keats.github.io/tera/docs/#base-template
     // https://github.com/Keats/tera/blob/3b2e96f624bd898cc96e964cd63194d58701ca4a/benches/templates.rs#L45
     // see also: askama
 
-    use tera::Context;
-    use tera::Tera;
-
-    let template = r#"<!doctype html>
-<html lang="en">
-  <head>
-    <title>{{ title }}</title>
-  </head>
-  <body>
-    <h1>You're confirmed!</h1>
-    <div id="content">
-      Hello, {{ name }}. To confirm your subscription, click
-      <a href="{{ link }}">here</a>.
-    </div>
-  </body>
-</html>"#;
-
-    let mut tera = Tera::default();
-    tera.autoescape_on(vec![]); // don't escape confirm_link
-    tera.add_raw_templates(vec![("confirm.html", template)])
-        .unwrap();
-
-    let mut context = Context::new();
-    context.insert("title", "Confirm your subscription");
-    context.insert("name", new_sub.name.as_ref());
-    context.insert("link", &confirm_link);
-
-    let html = tera.render("confirm.html", &context).unwrap();
-
-    email_client
-        .send_email(
-            &new_sub.email,
-            "foo",
-            // &format!("confirm at {confirm_link}").to_owned(),
-            &html,
-            &format!("confirm at {confirm_link}").to_owned(),
-        )
-        .await
+    let context = serde_json::json!({
+        "subject": CONFIRMATION_SUBJECT,
+        "name": new_sub.name.as_ref(),
+        "link": confirm_link,
+    });
+    let html = templates
+        .render("email/confirmation.html", &context)
+        .context("Failed to render confirmation email")?;
+    let text = templates
+        .render("email/confirmation.txt", &context)
+        .context("Failed to render confirmation email")?;
+
+    enqueue_email(
+        transaction,
+        &new_sub.email,
+        CONFIRMATION_SUBJECT,
+        &html,
+        &text,
+    )
+    .await
+    .context("Failed to enqueue confirmation email")?;
+    Ok(())
 }
 
 /// Fails if `email` not found in `subscriptions` table. The `id` returned may
@@ -148,17 +138,27 @@ pub async fn get_subscriber_id_from_email(
 }
 
 /// (extra function written beyond the scope of the book)
+///
+/// Only returns a token that can still be used, i.e. one that has neither been
+/// consumed, nor expired (created before `cutoff`)
 #[tracing::instrument(name = "Getting token of subscriber", skip(pool, id))]
 pub async fn get_subscriber_token(
     pool: &PgPool,
     id: &Uuid,
+    cutoff: DateTime<Utc>,
 ) -> Result<Option<String>, sqlx::Error> {
     let id = sqlx::query!(
         "
     SELECT subscription_token FROM subscription_tokens
-    WHERE subscriber_id = $1
+    WHERE
+        subscriber_id = $1 AND
+        consumed_at IS NULL AND
+        created_at > $2
+    ORDER BY created_at DESC
+    LIMIT 1
 ",
         id,
+        cutoff,
     )
     .fetch_optional(pool)
     .await
@@ -170,6 +170,12 @@ pub async fn get_subscriber_token(
     Ok(id)
 }
 
+/// 25-character alphanumeric, see `store_token`
+pub fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    (0..25).map(|_| rng.sample(Alphanumeric) as char).collect()
+}
+
 /// Print a complete error chain recursively
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
@@ -292,13 +298,16 @@ impl ResponseError for SubscribeError {
 /// `POST /subscribe`
 ///
 /// `form` is raw HTML, which is ultimately deserialized, in order to perform
-/// two SQL `INSERT` queries. Sends a confirmation email to the email address
-/// passed by the user.
+/// three SQL `INSERT` queries. Enqueues a confirmation email to the email
+/// address passed by the user.
 ///
 /// Success requires:
 ///     1. user input parsed
-///     2. user added to db AND user token added to db (transaction)
-///     3. email sent to user email
+///     2. user added to db AND user token added to db AND confirmation email
+///        added to outbox (transaction)
+///
+/// The email itself is sent by the delivery worker, so a failing email
+/// provider does not fail the request.
 ///
 /// Clients are expected to call `subscriptions/confirm` next.
 ///
@@ -331,7 +340,7 @@ impl ResponseError for SubscribeError {
     // wrapped by `tracing`
     name = "Adding new subscriber", // defaults to fn name
     // don't log passed args
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, templates, base_url, ttl),
     fields(
         // same syntax as info_span
         // should not be used in conjunction with TracingLogger, as TracingLogger generates its own ids
@@ -344,8 +353,9 @@ pub async fn subscribe(
     form: web::Form<SubscriberFormData>,
     // all subsequent args are inherited via App.app_data; thus arg types must be unique
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    templates: web::Data<Templates>,
     base_url: web::Data<AppBaseUrl>,
+    ttl: web::Data<ConfirmationTokenTtl>,
 ) -> Result<HttpResponse, SubscribeError> {
     // // with `log` feature, tracing events are redirected to `log`
     // // automatically
@@ -424,21 +434,36 @@ pub async fn subscribe(
 
     // extra: if user requests `subscriptions` more than once, email and token
     // should already be present in dbs, so just send another email (with stored
-    // token) and return early. this can be done before the transaction even
-    // begins
+    // token) and return early
     if let Ok(Some(id)) = get_subscriber_id_from_email(&pool, &new_sub.email).await {
-        let token = get_subscriber_token(&pool, &id)
+        let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+        let token = match get_subscriber_token(&pool, &id, ttl.cutoff())
             .await
             .context("Failed to get subscriber token")?
-            .context("Got empty token from db")?;
+        {
+            Some(token) => token,
+            // the previous link has expired (or been used), so issue a new one
+            None => {
+                let token = generate_subscription_token();
+                store_token(&mut transaction, id, &token)
+                    .await
+                    .context("Failed to store token")?;
+                token
+            }
+        };
 
-        send_confirmation_email(&email_client, new_sub, &base_url.0, &token)
+        enqueue_confirmation_email(&mut transaction, &templates, new_sub, &base_url.0, &token)
+            .await
+            .context("Failed to enqueue email")?;
+        transaction
+            .commit()
             .await
-            .context("Failed to send email")?;
+            .context("Failed to commit transaction")?;
         return Ok(HttpResponse::Ok().finish());
     };
 
-    // this transaction groups 2 additions into 2 tables
+    // this transaction groups 3 additions into 3 tables; the email is only sent
+    // if the subscriber and token are actually stored
     // wrap sqlx::Error in our own wrapper type, allowing early return with ?
     // `context` is like `map_err`, with extra context (duh)
     let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
@@ -450,10 +475,7 @@ pub async fn subscribe(
     // println!("{} {:?}", id, new_sub.email);
     // println!("storing token");
 
-    let token: String = {
-        let mut rng = thread_rng();
-        (0..25).map(|_| rng.sample(Alphanumeric) as char).collect()
-    };
+    let token = generate_subscription_token();
 
     // map_err is not needed because the function already returns a SubscribeError
     store_token(&mut transaction, id, &token)
@@ -462,6 +484,11 @@ pub async fn subscribe(
 
     // println!("storing token ok");
 
+    // we don't need map_err here; implementing `From` automagically enables ?
+    enqueue_confirmation_email(&mut transaction, &templates, new_sub, &base_url.0, &token)
+        .await
+        .context("Failed to enqueue email")?;
+
     transaction
         .commit()
         .await
@@ -469,20 +496,16 @@ pub async fn subscribe(
 
     // println!("transaction ok");
 
-    // we don't need map_err here; implementing `From` automagically enables ?
-    send_confirmation_email(&email_client, new_sub, &base_url.0, &token)
-        .await
-        .context("Failed to send email")?;
-
     Ok(HttpResponse::Ok().finish())
 }
 
-/// Add randomly generated `token` to `subscription_tokens` table
+/// Add randomly generated `token` to `subscription_tokens` table. The token
+/// expires after `ConfirmationTokenTtl`.
 #[tracing::instrument(
     name = "INSERTing new subscriber token into subscription_tokens table",
     skip(transaction, token)
 )]
-async fn store_token(
+pub async fn store_token(
     // pool: &PgPool,
     transaction: &mut Transaction<'_, Postgres>,
     id: Uuid,
@@ -6,11 +6,34 @@ use actix_web::web::Query;
 use actix_web::HttpResponse;
 use actix_web::ResponseError;
 use anyhow::Context;
+use chrono::DateTime;
+use chrono::Utc;
 use serde::Deserialize;
+use serde_json::json;
+use sqlx::Executor;
 use sqlx::PgPool;
+use sqlx::Postgres;
+use sqlx::Transaction;
 use uuid::Uuid;
 
+use super::enqueue_confirmation_email;
+use super::enqueue_welcome_email;
 use super::error_chain_fmt;
+use super::generate_subscription_token;
+use super::store_token;
+use crate::domain::NewSubscriber;
+use crate::domain::SubscriberEmail;
+use crate::domain::SubscriberName;
+use crate::startup::AppBaseUrl;
+use crate::startup::ConfirmationTokenTtl;
+use crate::startup::HmacSecret;
+use crate::templates::Templates;
+
+// confirmation tokens are single-use, and expire after `ConfirmationTokenTtl`.
+// an expired link is not a dead end though; the page offers to send a new one
+// (the old token is enough to identify the subscriber). consumed tokens are
+// kept around (until `delete_stale_tokens`), so that clicking the link twice
+// shows a friendly page instead of an error
 
 #[derive(Deserialize)]
 pub struct Parameters {
@@ -18,11 +41,23 @@ pub struct Parameters {
     subscription_token: String,
 }
 
+impl Parameters {
+    /// extra: basic string validation: ensure token is 25 chars long,
+    /// alphanumeric (no spaces). entropy could also be checked (but this is
+    /// probably overkill)
+    fn is_well_formed(&self) -> bool {
+        self.subscription_token.len() == 25
+            && self
+                .subscription_token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric())
+    }
+}
+
+/// Invalid tokens are not errors; they get a (401) page of their own, see
+/// `invalid_link_page`
 #[derive(thiserror::Error)]
 pub enum ConfirmError {
-    #[error("Token not found")]
-    ValidationError,
-
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -38,109 +73,282 @@ impl Debug for ConfirmError {
 }
 
 impl ResponseError for ConfirmError {
-    fn status_code(&self) -> actix_web::http::StatusCode {
-        match self {
-            Self::ValidationError => StatusCode::UNAUTHORIZED, // 400
-            _ => StatusCode::INTERNAL_SERVER_ERROR,            // 500
-        }
-    }
+    fn status_code(&self) -> actix_web::http::StatusCode { StatusCode::INTERNAL_SERVER_ERROR }
+}
+/// A row of `subscription_tokens`, along with the subscriber's `status`
+struct StoredToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+    status: String,
 }
-/// Fails if `token` not found in `subscription_tokens` table. The `id` returned
-/// may be empty, so this should be checked by the caller.
-#[tracing::instrument(name = "Getting id of new subscriber", skip(pool, token))]
-async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+
+/// Fails if `token` not found in `subscription_tokens` table. The row is
+/// locked until the end of `transaction`, so that a token can only be consumed
+/// once, even if the link is clicked twice concurrently.
+#[tracing::instrument(name = "Getting subscription token", skip(transaction, token))]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
     token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
+) -> Result<Option<StoredToken>, sqlx::Error> {
     // "What happens if the subscription token is well-formatted but non-existent
     // [in the db]?" -- what does 'well-formatted' mean? how can it be
     // non-existent?
 
-    let id = sqlx::query!(
+    let row = sqlx::query_as!(
+        StoredToken,
         "
-    SELECT subscriber_id FROM subscription_tokens
-    WHERE subscription_token = $1
+    SELECT t.subscriber_id, t.created_at, t.consumed_at, s.status
+    FROM subscription_tokens t
+    JOIN subscriptions s ON s.id = t.subscriber_id
+    WHERE t.subscription_token = $1
+    FOR UPDATE OF t
 ",
         token,
     )
-    .fetch_optional(pool)
-    .await?
-    // .map_err(|e| {
-    //     tracing::error!("bad query: {e:?}");
-    //     e
-    // })
-    .map(|u| u.subscriber_id);
-    Ok(id)
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row)
 }
 
 /// Idempotent
-#[tracing::instrument(name = "UPDATEing status of new subscriber", skip(pool))]
+#[tracing::instrument(name = "UPDATEing status of new subscriber", skip(transaction))]
 async fn confirm_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     id: Uuid,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        "
+    transaction
+        .execute(sqlx::query!(
+            "
     UPDATE subscriptions SET status = 'confirmed'
     WHERE id = $1
 ",
-        id,
-    )
-    .execute(pool)
-    .await
-    // .map_err(|e| {
-    //     tracing::error!("bad query: {e:?}");
-    //     e
-    // })
-    ?;
+            id,
+        ))
+        .await?;
     Ok(())
 }
 
+#[tracing::instrument(
+    name = "Marking subscription token as consumed",
+    skip(transaction, token)
+)]
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    transaction
+        .execute(sqlx::query!(
+            "
+    UPDATE subscription_tokens SET consumed_at = now()
+    WHERE subscription_token = $1
+",
+            token,
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Render one of the `confirm/*.html` pages
+fn confirm_page(
+    templates: &Templates,
+    status: StatusCode,
+    name: &str,
+    context: &serde_json::Value,
+) -> Result<HttpResponse, ConfirmError> {
+    let page = templates
+        .page_with_status(status, name, context)
+        .context("Failed to render confirmation page")?;
+    Ok(page)
+}
+
+/// Unknown (or malformed) token
+fn invalid_link_page(templates: &Templates) -> Result<HttpResponse, ConfirmError> {
+    confirm_page(
+        templates,
+        StatusCode::UNAUTHORIZED,
+        "confirm/invalid.html",
+        &json!({}),
+    )
+}
+
+/// Shown when a consumed token is used again
+fn already_used_page(
+    templates: &Templates,
+    status: &str,
+) -> Result<HttpResponse, ConfirmError> {
+    confirm_page(
+        templates,
+        StatusCode::OK,
+        "confirm/already_confirmed.html",
+        // otherwise, e.g. unsubscribed since
+        &json!({ "confirmed": status == "confirmed" }),
+    )
+}
+
 /// `GET /subscriptions/confirm`
 ///
 /// Given a token in `params`, get the user id associated with it, then change
-/// the user's `status` to confirmed.
+/// the user's `status` to confirmed, and mark the token as consumed. The
+/// welcome email (if enabled) is enqueued rather than sent here.
+///
+/// Clicking the link again is harmless; an expired link returns 410, with a
+/// form to request a new one (see `resend_confirmation`).
 ///
 /// Failure to parse `params` will automatically return 400.
-#[tracing::instrument(name = "Confirming new subscriber", skip(params, pool))]
+#[tracing::instrument(
+    name = "Confirming new subscriber",
+    skip(params, pool, templates, ttl, base_url, secret)
+)]
 pub async fn confirm(
     params: Query<Parameters>,
     pool: web::Data<PgPool>,
-    // ) -> HttpResponse {
+    templates: web::Data<Templates>,
+    ttl: web::Data<ConfirmationTokenTtl>,
+    base_url: web::Data<AppBaseUrl>,
+    secret: web::Data<HmacSecret>,
 ) -> Result<HttpResponse, ConfirmError> {
-    // extra: basic string validation: ensure token is 25 chars long, alphanumeric
-    // (no spaces). entropy could also be checked (but this is probably
-    // overkill)
-    if params.subscription_token.len() != 25 || params.subscription_token.contains(' ') {
-        return Ok(HttpResponse::InternalServerError().finish());
+    if !params.is_well_formed() {
+        return invalid_link_page(&templates);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let Some(token) = get_token(&mut transaction, &params.subscription_token)
+        .await
+        .context("Failed to get subscription token")?
+    else {
+        return invalid_link_page(&templates);
+    };
+
+    if token.consumed_at.is_some() {
+        return already_used_page(&templates, &token.status);
     }
 
-    let id = get_subscriber_id_from_token(&pool, &params.subscription_token)
+    if token.created_at < ttl.cutoff() {
+        // the token is needed to identify the subscriber, and is replaced once
+        // a new one is sent
+        return confirm_page(
+            &templates,
+            StatusCode::GONE,
+            "confirm/expired.html",
+            &json!({ "token": params.subscription_token }),
+        );
+    }
+
+    confirm_subscriber(&mut transaction, token.subscriber_id)
         .await
-        .context("Failed to get subscriber id from token")?
-        .ok_or(ConfirmError::ValidationError)?;
+        .context("Failed to confirm subscriber")?;
+    consume_token(&mut transaction, &params.subscription_token)
+        .await
+        .context("Failed to consume token")?;
+    // sent by the delivery worker, once the transaction is committed
+    enqueue_welcome_email(&mut transaction, token.subscriber_id, &base_url.0, &secret)
+        .await
+        .context("Failed to enqueue welcome email")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    confirm_page(
+        &templates,
+        StatusCode::OK,
+        "confirm/success.html",
+        &json!({}),
+    )
+}
 
-    // extra: prevent user from being confirmed twice (this is only a formality,
-    // because `confirm_subscriber` is actually idempotent)
-    if sqlx::query!(
+/// Returns `(name, email)` of the subscriber
+#[tracing::instrument(name = "Getting subscriber to resend confirmation", skip(transaction))]
+async fn get_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+) -> Result<(String, String), sqlx::Error> {
+    let row = sqlx::query!(
         "
-    SELECT status FROM subscriptions
+    SELECT name, email FROM subscriptions
     WHERE id = $1
 ",
         id,
     )
-    .fetch_optional(pool.as_ref())
-    .await
-    .unwrap()
-    .map(|u| u.status)
-        == Some("confirmed".to_owned())
-    {
-        return Ok(HttpResponse::InternalServerError().finish());
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok((row.name, row.email))
+}
+
+/// `POST /subscriptions/confirm/resend`
+///
+/// Submitted from the "link expired" page. Replaces the (expired) token with a
+/// new one, and enqueues another confirmation email.
+#[tracing::instrument(
+    name = "Resending confirmation email",
+    skip(form, pool, templates, base_url)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<Parameters>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<AppBaseUrl>,
+) -> Result<HttpResponse, ConfirmError> {
+    if !form.is_well_formed() {
+        return invalid_link_page(&templates);
+    }
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+
+    let Some(token) = get_token(&mut transaction, &form.subscription_token)
+        .await
+        .context("Failed to get subscription token")?
+    else {
+        return invalid_link_page(&templates);
     };
 
-    confirm_subscriber(&pool, id)
+    if token.consumed_at.is_some() {
+        return already_used_page(&templates, &token.status);
+    }
+
+    let (name, email) = get_subscriber(&mut transaction, token.subscriber_id)
         .await
-        .context("Failed to confirm subscriber")?;
+        .context("Failed to get subscriber")?;
+    // both were validated on insertion
+    let new_sub = NewSubscriber {
+        name: SubscriberName::parse(name).map_err(anyhow::Error::msg)?,
+        email: SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?,
+    };
 
-    Ok(HttpResponse::Ok().finish())
+    let new_token = generate_subscription_token();
+    store_token(&mut transaction, token.subscriber_id, &new_token)
+        .await
+        .context("Failed to store token")?;
+    transaction
+        .execute(sqlx::query!(
+            "
+    DELETE FROM subscription_tokens
+    WHERE subscription_token = $1
+",
+            form.subscription_token,
+        ))
+        .await
+        .context("Failed to delete expired token")?;
+    enqueue_confirmation_email(
+        &mut transaction,
+        &templates,
+        new_sub,
+        &base_url.0,
+        &new_token,
+    )
+    .await
+    .context("Failed to enqueue email")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    confirm_page(
+        &templates,
+        StatusCode::OK,
+        "confirm/resent.html",
+        &json!({}),
+    )
 }
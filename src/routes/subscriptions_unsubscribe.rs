@@ -0,0 +1,265 @@
+use std::fmt::Debug;
+
+use actix_web::http::StatusCode;
+use actix_web::web;
+use actix_web::web::Query;
+use actix_web::HttpResponse;
+use actix_web::ResponseError;
+use anyhow::Context;
+use hmac::Hmac;
+use hmac::Mac;
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Executor;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::error_chain_fmt;
+use crate::startup::HmacSecret;
+use crate::templates::Templates;
+
+// unlike confirmation tokens, unsubscribe tokens are not stored in the db;
+// instead, the subscriber id is signed with our `HmacSecret`, so that a link
+// can be generated for every delivered issue without an extra table. the
+// tradeoff is that a link cannot be revoked (short of rotating the secret)
+
+/// MAC over `subscriber_id`, keyed with our secret
+fn sign(
+    subscriber_id: Uuid,
+    secret: &HmacSecret,
+) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(format!("unsubscribe={subscriber_id}").as_bytes());
+    mac
+}
+
+/// Per-subscriber token, to be embedded in every delivered issue. See
+/// `unsubscribe_link`.
+pub fn unsubscribe_token(
+    subscriber_id: Uuid,
+    secret: &HmacSecret,
+) -> String {
+    hex::encode(sign(subscriber_id, secret).finalize().into_bytes())
+}
+
+/// Personalised link to `GET /subscriptions/unsubscribe`
+pub fn unsubscribe_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    secret: &HmacSecret,
+) -> String {
+    let token = unsubscribe_token(subscriber_id, secret);
+    format!("{base_url}/subscriptions/unsubscribe?subscriber_id={subscriber_id}&token={token}")
+}
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    /// Byte slice encoded as hex string, generated by `unsubscribe_token`
+    token: String,
+}
+
+impl UnsubscribeParameters {
+    /// Fails if `token` was not generated from `subscriber_id` with our secret
+    fn verify(
+        &self,
+        secret: &HmacSecret,
+    ) -> Result<(), anyhow::Error> {
+        let tag = hex::decode(&self.token)?;
+        // constant-time comparison
+        sign(self.subscriber_id, secret).verify_slice(&tag)?;
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("Invalid unsubscribe link")]
+    ValidationError(#[source] anyhow::Error),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for UnsubscribeError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        error_chain_fmt(self, f)?;
+        Ok(())
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::UNAUTHORIZED, // 401
+            _ => StatusCode::INTERNAL_SERVER_ERROR,               // 500
+        }
+    }
+}
+
+/// Returns `(email, status)`, or `None` if the subscriber has been removed
+#[tracing::instrument(name = "Getting subscriber to unsubscribe", skip(pool))]
+async fn get_subscriber(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<Option<(String, String)>, sqlx::Error> {
+    let row = sqlx::query!(
+        "
+    SELECT email, status FROM subscriptions
+    WHERE id = $1
+",
+        id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|r| (r.email, r.status));
+    Ok(row)
+}
+
+/// Idempotent. Pending deliveries to the subscriber are dropped as well.
+#[tracing::instrument(name = "UPDATEing status of subscriber to unsubscribed", skip(pool))]
+async fn unsubscribe_subscriber(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    transaction
+        .execute(sqlx::query!(
+            "
+    UPDATE subscriptions SET status = 'unsubscribed'
+    WHERE id = $1
+",
+            id,
+        ))
+        .await?;
+    transaction
+        .execute(sqlx::query!(
+            "
+    DELETE FROM issue_delivery_queue
+    WHERE subscriber_id = $1
+",
+            id,
+        ))
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn render(
+    templates: &Templates,
+    name: &str,
+    context: &serde_json::Value,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let page = templates
+        .page(name, context)
+        .context("Failed to render unsubscribe page")?;
+    Ok(page)
+}
+
+/// `GET /subscriptions/unsubscribe`
+///
+/// Linked from every delivered issue. Only asks for confirmation; the actual
+/// unsubscription is done by `POST`, since link scanners (and prefetching
+/// browsers) are free to follow any link in an email.
+#[tracing::instrument(
+    name = "Requesting unsubscription",
+    skip(params, pool, secret, templates)
+)]
+pub async fn unsubscribe_form(
+    params: Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    params
+        .verify(&secret)
+        .map_err(UnsubscribeError::ValidationError)?;
+
+    let (email, status) = get_subscriber(&pool, params.subscriber_id)
+        .await
+        .context("Failed to get subscriber")?
+        .context("No subscriber found")
+        .map_err(UnsubscribeError::ValidationError)?;
+
+    if status == "unsubscribed" {
+        return render(
+            &templates,
+            "unsubscribe/done.html",
+            &json!({ "already": true }),
+        );
+    }
+
+    render(
+        &templates,
+        "unsubscribe/form.html",
+        &json!({
+            "email": email,
+            "subscriber_id": params.subscriber_id,
+            "token": params.token,
+        }),
+    )
+}
+
+/// Body sent by mail clients that honour the `List-Unsubscribe-Post` header
+/// (RFC 8058), i.e. `List-Unsubscribe=One-Click`. Our own form sends an empty
+/// body.
+#[derive(Deserialize)]
+pub struct OneClickFormData {
+    #[serde(rename = "List-Unsubscribe")]
+    list_unsubscribe: Option<String>,
+}
+
+/// `POST /subscriptions/unsubscribe`
+///
+/// Set the subscriber's `status` to `unsubscribed`, so that they are skipped by
+/// `enqueue_delivery_tasks`. Subscribing again (and confirming) reverses this.
+///
+/// Also serves as the one-click endpoint declared in `List-Unsubscribe`, so
+/// the signed query params must be the only credentials required; no session,
+/// cookie or CSRF token can be expected from a mail client. Such requests get
+/// an empty 200, since there is nobody to read the page (and a redirect would
+/// not be followed).
+#[tracing::instrument(
+    name = "Unsubscribing subscriber",
+    skip(params, form, pool, secret, templates)
+)]
+pub async fn unsubscribe(
+    params: Query<UnsubscribeParameters>,
+    // a missing/malformed body should not prevent unsubscription
+    form: Option<web::Form<OneClickFormData>>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    params
+        .verify(&secret)
+        .map_err(UnsubscribeError::ValidationError)?;
+
+    get_subscriber(&pool, params.subscriber_id)
+        .await
+        .context("Failed to get subscriber")?
+        .context("No subscriber found")
+        .map_err(UnsubscribeError::ValidationError)?;
+
+    unsubscribe_subscriber(&pool, params.subscriber_id)
+        .await
+        .context("Failed to unsubscribe subscriber")?;
+
+    let one_click = form
+        .and_then(|f| f.0.list_unsubscribe)
+        .is_some_and(|v| v == "One-Click");
+    if one_click {
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    render(
+        &templates,
+        "unsubscribe/done.html",
+        &json!({ "already": false }),
+    )
+}
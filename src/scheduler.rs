@@ -0,0 +1,88 @@
+// this worker is solely responsible for publishing scheduled issues once their
+// `send_at` has passed. publishing works just like `publish_newsletter`: the
+// issue is marked as published, and one delivery per confirmed subscriber is
+// enqueued, to be picked up by the delivery worker
+
+use std::time::Duration;
+
+use sqlx::Executor;
+use sqlx::PgPool;
+
+use crate::configuration::Settings;
+use crate::routes::enqueue_delivery_tasks;
+use crate::shutdown::Shutdown;
+use crate::startup::get_connection_pool;
+
+pub enum SchedulerOutcome {
+    NoIssuesDue,
+    IssuesDue,
+}
+
+/// Publish a single due issue, if any. Like `start_delivery`, the row is locked
+/// (and skipped by other workers) until the transaction is committed.
+#[tracing::instrument(skip_all, err)]
+pub async fn try_publish_scheduled_issue(pool: &PgPool) -> Result<SchedulerOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let Some(issue) = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE
+            status = 'scheduled' AND
+            send_at <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(SchedulerOutcome::NoIssuesDue);
+    };
+
+    tracing::info!("publishing scheduled issue {}", issue.newsletter_issue_id);
+
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET
+                status = 'published',
+                published_at = now()
+            WHERE newsletter_issue_id = $1
+            "#,
+            issue.newsletter_issue_id,
+        ))
+        .await?;
+    enqueue_delivery_tasks(&mut transaction, issue.newsletter_issue_id).await?;
+    transaction.commit().await?;
+
+    Ok(SchedulerOutcome::IssuesDue)
+}
+
+async fn publish_scheduled_loop(
+    pool: &PgPool,
+    shutdown: &Shutdown,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_triggered() {
+        match try_publish_scheduled_issue(pool).await {
+            Err(_) => shutdown.sleep(Duration::from_secs(1)).await,
+            // issues can only be scheduled to the minute, so this is plenty
+            Ok(SchedulerOutcome::NoIssuesDue) => shutdown.sleep(Duration::from_secs(10)).await,
+            Ok(SchedulerOutcome::IssuesDue) => {}
+        }
+    }
+    Ok(())
+}
+
+/// To be run as a separate worker, outside the main API. Returns once
+/// `shutdown` is triggered.
+pub async fn init_scheduler_worker(
+    cfg: Settings,
+    shutdown: Shutdown,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&cfg.database);
+    publish_scheduled_loop(&pool, &shutdown).await
+}
@@ -0,0 +1,99 @@
+// coordinated shutdown. `main` waits for SIGTERM (sent by the container
+// runtime on every deploy) or ctrl-c, then tells every component to stop via a
+// `Shutdown`. the server stops accepting connections, and the background
+// workers finish what they are doing (but don't start anything new). whatever
+// is still running after `shutdown_timeout_secs` is aborted; since every
+// delivery is a single transaction, an aborted delivery is simply rolled back,
+// and picked up again after the restart
+
+use std::time::Duration;
+
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::watch;
+
+/// Handed to every component; see `channel`
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Whether the component should stop, i.e. not start any new work
+    pub fn is_triggered(&self) -> bool { *self.0.borrow() }
+
+    /// Resolves once shutdown is triggered, or the `ShutdownTrigger` has been
+    /// dropped. Meant to be raced against sleeps and other idle waits.
+    pub async fn triggered(&self) {
+        // `wait_for` requires `&mut`, so that the caller doesn't have to
+        let mut rx = self.0.clone();
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Sleep for `duration`, or until shutdown is triggered, whichever comes
+    /// first
+    pub async fn sleep(
+        &self,
+        duration: Duration,
+    ) {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {}
+            _ = self.triggered() => {}
+        }
+    }
+}
+
+/// Held by `main`
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) { self.0.send_replace(true); }
+}
+
+pub fn channel() -> (ShutdownTrigger, Shutdown) {
+    let (tx, rx) = watch::channel(false);
+    (ShutdownTrigger(tx), Shutdown(rx))
+}
+
+/// Resolves on SIGTERM or ctrl-c (SIGINT), returning the name of the signal
+pub async fn wait_for_signal() -> Result<&'static str, std::io::Error> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = sigterm.recv() => Ok("SIGTERM"),
+        res = tokio::signal::ctrl_c() => res.map(|_| "SIGINT"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::channel;
+
+    #[tokio::test]
+    async fn triggered_resolves_for_every_clone() {
+        let (trigger, shutdown) = channel();
+        let clone = shutdown.clone();
+        assert!(!shutdown.is_triggered());
+
+        let waiting = tokio::spawn(async move { clone.triggered().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        trigger.trigger();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(shutdown.is_triggered());
+        // already triggered
+        shutdown.triggered().await;
+    }
+
+    #[tokio::test]
+    async fn dropped_trigger_counts_as_triggered() {
+        let (trigger, shutdown) = channel();
+        drop(trigger);
+        tokio::time::timeout(Duration::from_secs(1), shutdown.triggered())
+            .await
+            .unwrap();
+    }
+}
@@ -1,9 +1,12 @@
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
 use actix_web::dev::Server;
+use actix_web::dev::ServerHandle;
 use actix_web::web;
 use actix_web::web::Data;
 use actix_web::App;
@@ -11,6 +14,8 @@ use actix_web::HttpServer;
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
 use actix_web_lab::middleware::from_fn;
+use chrono::DateTime;
+use chrono::Utc;
 use secrecy::ExposeSecret;
 use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
@@ -20,19 +25,48 @@ use tracing_actix_web::TracingLogger;
 use crate::authentication::reject_anonymous_users;
 use crate::configuration::DatabaseSettings;
 use crate::configuration::Settings;
-use crate::email_client::EmailClient;
+use crate::email_client::EmailProvider;
 use crate::routes::admin_dashboard;
+use crate::routes::atom;
+use crate::routes::cancel_issue;
 use crate::routes::change_password;
 use crate::routes::change_password_form;
 use crate::routes::confirm;
+use crate::routes::create_draft;
+use crate::routes::delete_draft;
+use crate::routes::delivery_status;
+use crate::routes::discard_failed_delivery;
+use crate::routes::drafts;
+use crate::routes::edit_draft;
+use crate::routes::failed_deliveries;
 use crate::routes::health_check;
 use crate::routes::home;
+use crate::routes::issue;
+use crate::routes::issue_delivery_status;
+use crate::routes::issues;
 use crate::routes::login;
 use crate::routes::login_form;
 use crate::routes::logout;
 use crate::routes::newsletter_form;
+use crate::routes::preview_draft;
+use crate::routes::preview_newsletter;
+use crate::routes::publish_draft;
 use crate::routes::publish_newsletter;
+use crate::routes::reschedule_issue;
+use crate::routes::resend_confirmation;
+use crate::routes::retry_failed_delivery;
+use crate::routes::rss;
+use crate::routes::scheduled_issues;
+use crate::routes::send_test_draft;
+use crate::routes::send_test_issue;
 use crate::routes::subscribe;
+use crate::routes::subscriber_deliveries;
+use crate::routes::unsubscribe;
+use crate::routes::unsubscribe_form;
+use crate::routes::update_draft;
+use crate::routes::update_welcome_email;
+use crate::routes::welcome_email_form;
+use crate::templates::Templates;
 
 /// Wrapper for actix's `Server` with access to the bound port. Not to be
 /// confused with actix's `App`!
@@ -42,6 +76,7 @@ pub struct Application {
     /// Contains the following components: TCP listener (randomised port), db
     /// pool (fixed port), and email client
     server: Server,
+    email_client: Arc<dyn EmailProvider>,
 }
 
 impl Application {
@@ -96,22 +131,40 @@ impl Application {
         //     timeout,
         // );
         let email_client = cfg.email_client.client();
+        let shutdown_timeout = cfg.application.shutdown_timeout();
+        let confirmation_token_ttl = cfg.application.confirmation_token_ttl();
+        // a broken template prevents startup (unless hot reloading)
+        let templates = Templates::new(cfg.application.hot_reload_templates)?;
 
         let server = run(
             listener,
             pool,
-            email_client,
+            email_client.clone(),
             cfg.application.base_url,
             cfg.application.hmac_secret,
             cfg.redis_uri,
+            shutdown_timeout,
+            confirmation_token_ttl,
+            templates,
         )
         .await?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            email_client,
+        })
     }
 
     pub fn get_port(&self) -> u16 { self.port }
 
+    /// The client used by the server, to be shared with the delivery worker;
+    /// see `init_delivery_worker`
+    pub fn email_client(&self) -> Arc<dyn EmailProvider> { self.email_client.clone() }
+
+    /// For stopping the server (gracefully) from outside
+    pub fn handle(&self) -> ServerHandle { self.server.handle() }
+
     /// Because this consumes `self`, this should be the final function call (or
     /// passed to `tokio::spawn`)
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> { self.server.await }
@@ -125,6 +178,16 @@ pub fn get_connection_pool(db_cfg: &DatabaseSettings) -> PgPool {
 /// conflict with one another when passed around by `Data`)
 pub struct AppBaseUrl(pub String);
 
+/// How long a confirmation link stays valid; see
+/// `ApplicationSettings::confirmation_token_ttl_hours`
+#[derive(Clone, Copy, Debug)]
+pub struct ConfirmationTokenTtl(pub Duration);
+
+impl ConfirmationTokenTtl {
+    /// Tokens created before this have expired
+    pub fn cutoff(&self) -> DateTime<Utc> { Utc::now() - self.0 }
+}
+
 /// Message authentication guarantees that the message has not been modified in
 /// transit, and allows identity of the sender to be verified. We use HMAC
 /// (specified in RFC2104).
@@ -136,14 +199,19 @@ pub struct HmacSecret(pub Secret<String>);
 // Requires a running Redis instance (?).
 ///
 /// Declares all API endpoints.
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     // address: &str, // fixed port
     listener: TcpListener,
     pool: PgPool,
-    email_client: EmailClient,
+    email_client: Arc<dyn EmailProvider>,
     base_url: String,
     hmac_secret: Secret<String>,
     redis_uri: Secret<String>,
+    // in-flight requests are given this long to finish after `ServerHandle::stop`
+    shutdown_timeout: Duration,
+    confirmation_token_ttl: Duration,
+    templates: Templates,
 ) -> Result<Server, anyhow::Error> {
     // email newsletter (e.g. MailChimp)
 
@@ -183,7 +251,10 @@ pub async fn run(
     // `Data` is externally an `Arc` (for sharing/cloning), internally a `HashMap`
     // (for wrapping arbitrary types)
     let pool = web::Data::new(pool);
-    let email_client = web::Data::new(email_client);
+    // `Data::from` avoids double wrapping the `Arc`, and is the only way to share
+    // a trait object
+    let email_client: Data<dyn EmailProvider> = Data::from(email_client);
+    let templates = Data::new(templates);
 
     // note the closure; "`actix-web` will spin up a worker process for each
     // available core on your machine. Each worker runs its own copy of the
@@ -207,8 +278,21 @@ pub async fn run(
             // remember, the guard must match the client's request type
             .route("/", web::get().to(home))
             .route("/health_check", web::get().to(health_check))
+            .route("/issues", web::get().to(issues))
+            .route("/feed.xml", web::get().to(atom))
+            .route("/rss.xml", web::get().to(rss))
+            .route("/issues/{id}", web::get().to(issue))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post().to(resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
             // .route("/newsletters", web::post().to(publish))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
@@ -220,7 +304,58 @@ pub async fn run(
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(logout))
                     .route("/newsletters", web::get().to(newsletter_form))
-                    .route("/newsletters", web::post().to(publish_newsletter)),
+                    .route("/newsletters", web::post().to(publish_newsletter))
+                    .route("/newsletters/preview", web::post().to(preview_newsletter))
+                    .route("/newsletters/drafts", web::get().to(drafts))
+                    .route("/newsletters/drafts", web::post().to(create_draft))
+                    .route("/newsletters/drafts/{id}", web::get().to(edit_draft))
+                    .route("/newsletters/drafts/{id}", web::post().to(update_draft))
+                    .route(
+                        "/newsletters/drafts/{id}/delete",
+                        web::post().to(delete_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{id}/preview",
+                        web::get().to(preview_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{id}/publish",
+                        web::post().to(publish_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{id}/test",
+                        web::post().to(send_test_draft),
+                    )
+                    .route("/newsletters/scheduled", web::get().to(scheduled_issues))
+                    .route("/newsletters/test", web::post().to(send_test_issue))
+                    .route(
+                        "/newsletters/scheduled/reschedule",
+                        web::post().to(reschedule_issue),
+                    )
+                    .route(
+                        "/newsletters/scheduled/cancel",
+                        web::post().to(cancel_issue),
+                    )
+                    .route("/welcome", web::get().to(welcome_email_form))
+                    .route("/welcome", web::post().to(update_welcome_email))
+                    .route("/deliveries", web::get().to(delivery_status))
+                    .route(
+                        "/deliveries/issues/{id}",
+                        web::get().to(issue_delivery_status),
+                    )
+                    .route(
+                        "/deliveries/subscriber",
+                        web::get().to(subscriber_deliveries),
+                    )
+                    .route("/deliveries/failed", web::get().to(failed_deliveries))
+                    .route(
+                        "/deliveries/failed/retry",
+                        web::post().to(retry_failed_delivery),
+                    )
+                    .route(
+                        "/deliveries/failed/discard",
+                        web::post().to(discard_failed_delivery),
+                    ),
             )
             // with `.app_data`, global state (e.g. db connection, http client(s)) is made available
             // to all endpoints, if specified as args. args passed must either implement
@@ -228,8 +363,10 @@ pub async fn run(
             // associated fields of the struct can be shared across the app.
             .app_data(pool.clone())
             .app_data(email_client.clone())
+            .app_data(templates.clone())
             // .app_data(base_url.clone())
             .app_data(Data::new(AppBaseUrl(base_url.clone())))
+            .app_data(Data::new(ConfirmationTokenTtl(confirmation_token_ttl)))
             .app_data(Data::new(HmacSecret(hmac_secret.clone())))
 
         // .route("/", web::get().to(greet))
@@ -247,6 +384,9 @@ pub async fn run(
     })
     // .bind(address)? // if no port specified, "invalid socket address"
     .listen(listener)?
+    // shutdown is coordinated by `main` (see `shutdown`), via `ServerHandle`
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .run();
 
     // server.await // async return -- caller uses foo().await
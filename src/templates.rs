@@ -0,0 +1,231 @@
+// every HTML page (and email) is rendered from a template in
+// `src/routes/templates`. pages extend `layout.html`, which also shows any
+// flash messages passed as `messages`.
+//
+// templates are embedded in the binary at compile time, so that the deployed
+// image doesn't need to ship (or locate) the template files. they are parsed
+// once, at startup, and shared by all workers via `web::Data<Templates>`. when
+// developing locally, `hot_reload_templates` re-reads them from disk on every
+// render instead, so that changes show up without a rebuild
+
+use std::sync::RwLock;
+
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use serde::Serialize;
+use tera::Context;
+use tera::Tera;
+
+/// Only used for hot reloading, i.e. when running from the source tree
+const TEMPLATE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/routes/templates");
+
+/// `(name, source)`; names are paths relative to `src/routes/templates`
+macro_rules! templates {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_str!(concat!("routes/templates/", $name)))),*]
+    };
+}
+
+/// Must list every file in `TEMPLATE_DIR`; see
+/// `embedded_templates_are_complete`
+const TEMPLATES: &[(&str, &str)] = templates![
+    "layout.html",
+    "home.html",
+    "login.html",
+    "admin/dashboard.html",
+    "admin/password.html",
+    "admin/deliveries/failed.html",
+    "admin/deliveries/issue.html",
+    "admin/deliveries/status.html",
+    "admin/deliveries/subscriber.html",
+    "confirm/already_confirmed.html",
+    "confirm/expired.html",
+    "confirm/invalid.html",
+    "confirm/resent.html",
+    "confirm/success.html",
+    "email/confirmation.html",
+    "email/confirmation.txt",
+    "issues/_list.html",
+    "issues/issue.html",
+    "issues/list.html",
+    "newsletters/drafts.html",
+    "newsletters/edit_draft.html",
+    "newsletters/form.html",
+    "newsletters/preview.html",
+    "newsletters/scheduled.html",
+    "newsletters/test.html",
+    "newsletters/welcome.html",
+    "unsubscribe/done.html",
+    "unsubscribe/form.html",
+];
+
+/// Registry of all templates. Autoescaping is on for `.html` templates, so
+/// values only need `| safe` if they are known to be safe (e.g. sanitised
+/// issue content).
+pub struct Templates {
+    // only written to when hot reloading
+    tera: RwLock<Tera>,
+    hot_reload: bool,
+}
+
+impl Templates {
+    /// Fails if any template cannot be parsed, e.g. bad syntax, or a missing
+    /// parent template
+    pub fn new(hot_reload: bool) -> Result<Self, tera::Error> {
+        let mut tera = match hot_reload {
+            true => Tera::new(&format!("{TEMPLATE_DIR}/**/*"))?,
+            false => {
+                let mut tera = Tera::default();
+                tera.add_raw_templates(TEMPLATES.iter().copied())?;
+                tera
+            }
+        };
+        tera.autoescape_on(vec![".html"]);
+        // tera's default also escapes `/`, which is harmless, but makes urls and
+        // plain text unreadable in the page source. every attribute is quoted,
+        // so `& < > " '` is enough
+        tera.set_escape_fn(htmlescape::encode_minimal);
+        Ok(Self {
+            tera: RwLock::new(tera),
+            hot_reload,
+        })
+    }
+
+    /// `context` is anything that serializes to a map, typically
+    /// `serde_json::json!({...})`
+    pub fn render<C: Serialize>(
+        &self,
+        name: &str,
+        context: &C,
+    ) -> Result<String, tera::Error> {
+        if self.hot_reload {
+            // a broken template is only reported when rendered, rather than at
+            // startup
+            self.tera.write().unwrap().full_reload()?;
+        }
+        self.tera
+            .read()
+            .unwrap()
+            .render(name, &Context::from_serialize(context)?)
+    }
+
+    /// Render an HTML page with the given `status`
+    pub fn page_with_status<C: Serialize>(
+        &self,
+        status: StatusCode,
+        name: &str,
+        context: &C,
+    ) -> Result<HttpResponse, tera::Error> {
+        let body = self.render(name, context)?;
+        Ok(HttpResponse::build(status)
+            .content_type(ContentType::html())
+            .body(body))
+    }
+
+    /// Render an HTML page (200)
+    pub fn page<C: Serialize>(
+        &self,
+        name: &str,
+        context: &C,
+    ) -> Result<HttpResponse, tera::Error> {
+        self.page_with_status(StatusCode::OK, name, context)
+    }
+}
+
+/// To be passed as `messages`, see `layout.html`. Like any other value, they
+/// are escaped.
+pub fn flash_contents(flash_messages: &IncomingFlashMessages) -> Vec<&str> {
+    flash_messages.iter().map(|m| m.content()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::Templates;
+    use super::TEMPLATES;
+    use super::TEMPLATE_DIR;
+
+    /// Paths of all files under `dir`, relative to `root`
+    fn list_files(
+        root: &Path,
+        dir: &Path,
+        files: &mut Vec<String>,
+    ) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            match path.is_dir() {
+                true => list_files(root, &path, files),
+                false => files.push(path.strip_prefix(root).unwrap().display().to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn embedded_templates_are_complete() {
+        let mut on_disk = vec![];
+        list_files(
+            Path::new(TEMPLATE_DIR),
+            Path::new(TEMPLATE_DIR),
+            &mut on_disk,
+        );
+        on_disk.sort();
+        let mut embedded: Vec<_> = TEMPLATES.iter().map(|(name, _)| name.to_string()).collect();
+        embedded.sort();
+        assert_eq!(on_disk, embedded);
+    }
+
+    #[test]
+    fn all_templates_parse() {
+        Templates::new(false).unwrap();
+        Templates::new(true).unwrap();
+    }
+
+    #[test]
+    fn html_is_escaped_but_links_are_not() {
+        let templates = Templates::new(false).unwrap();
+        let ctx = json!({
+            "subject": "Confirm your subscription",
+            "name": "<b>john</b>",
+            "link": "https://example.com/subscriptions/confirm?subscription_token=abc",
+        });
+
+        let html = templates.render("email/confirmation.html", &ctx).unwrap();
+        assert!(html.contains("&lt;b&gt;john&lt;/b&gt;"));
+        assert!(html.contains(
+            r#"href="https://example.com/subscriptions/confirm?subscription_token=abc""#
+        ));
+
+        // plain text is never escaped
+        let text = templates.render("email/confirmation.txt", &ctx).unwrap();
+        assert!(text.contains("<b>john</b>"));
+    }
+
+    #[test]
+    fn flash_messages_are_escaped() {
+        let templates = Templates::new(false).unwrap();
+        let html = templates
+            .render(
+                "admin/password.html",
+                &json!({ "messages": ["<script>alert(1)</script>"] }),
+            )
+            .unwrap();
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<p><i>&lt;script&gt;"));
+    }
+
+    #[test]
+    fn hot_reload_renders_the_same() {
+        let ctx = json!({ "username": "admin" });
+        let embedded = Templates::new(false).unwrap();
+        let reloaded = Templates::new(true).unwrap();
+        assert_eq!(
+            embedded.render("admin/dashboard.html", &ctx).unwrap(),
+            reloaded.render("admin/dashboard.html", &ctx).unwrap(),
+        );
+    }
+}
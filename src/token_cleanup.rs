@@ -0,0 +1,69 @@
+// this worker is solely responsible for periodically dropping stale rows from
+// the `subscription_tokens` table, much like `init_expiry_worker` does for
+// idempotency keys.
+//
+// expired and consumed tokens are not deleted right away: an expired link
+// should still lead to the "resend?" page, and a consumed one to the "already
+// confirmed" page. after twice the TTL, nobody is expected to click them
+// anymore
+
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+use crate::configuration::Settings;
+use crate::shutdown::Shutdown;
+use crate::startup::get_connection_pool;
+
+/// Delete tokens created more than `2 * ttl` ago, whether consumed or not.
+/// Returns the number of tokens deleted.
+pub async fn delete_stale_tokens(
+    pool: &PgPool,
+    ttl: Duration,
+) -> Result<u64, anyhow::Error> {
+    let cutoff = chrono::Utc::now() - chrono::Duration::from_std(ttl * 2)?;
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE created_at < $1
+"#,
+        cutoff,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(deleted)
+}
+
+async fn cleanup_loop(
+    pool: &PgPool,
+    ttl: Duration,
+    shutdown: &Shutdown,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_triggered() {
+        match delete_stale_tokens(pool, ttl).await {
+            Err(e) => {
+                tracing::error!("failed to delete stale tokens: {e:?}");
+                shutdown.sleep(Duration::from_secs(60)).await
+            }
+            Ok(n) => {
+                if n > 0 {
+                    tracing::info!("deleted {n} stale confirmation tokens");
+                }
+                shutdown.sleep(Duration::from_secs(3600)).await
+            }
+        }
+    }
+    Ok(())
+}
+
+/// To be run as a separate worker, outside the main API. Returns once
+/// `shutdown` is triggered.
+pub async fn init_token_cleanup_worker(
+    cfg: Settings,
+    shutdown: Shutdown,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&cfg.database);
+    let ttl = cfg.application.confirmation_token_ttl();
+    cleanup_loop(&pool, ttl, &shutdown).await
+}
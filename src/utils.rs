@@ -20,6 +20,14 @@ where
     actix_web::error::ErrorBadRequest(e)
 }
 
+/// Convert arbitrary error types to `actix_web::Error` with HTTP 404
+pub fn error_404<T>(e: T) -> actix_web::Error
+where
+    T: Debug + Display + 'static,
+{
+    actix_web::error::ErrorNotFound(e)
+}
+
 /// Don't forget the leading slash!
 pub fn redirect(location: &str) -> HttpResponse {
     HttpResponse::SeeOther()
@@ -0,0 +1,275 @@
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::check_redirect;
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+/// Counts shown on `/admin/deliveries/issues/{id}`, in the order queued,
+/// retrying, sent, skipped, failed
+async fn get_counts(
+    app: &TestApp,
+    id: Uuid,
+) -> [u32; 5] {
+    let html = app
+        .get_delivery_status(&format!("/issues/{id}"))
+        .await
+        .text()
+        .await
+        .unwrap();
+    ["queued", "retrying", "sent", "skipped", "failed"].map(|field| {
+        let start = format!(r#"<td id="{field}">"#);
+        let value = html
+            .split(&start)
+            .nth(1)
+            .unwrap()
+            .split('<')
+            .next()
+            .unwrap();
+        value.parse().unwrap()
+    })
+}
+
+#[tokio::test]
+async fn delivery_status_requires_login() {
+    let app = spawn_app().await;
+    let resp = app.get_delivery_status("").await;
+    check_redirect(&resp, "/login");
+    let resp = app
+        .get_delivery_status(&format!("/issues/{}", Uuid::new_v4()))
+        .await;
+    check_redirect(&resp, "/login");
+}
+
+#[tokio::test]
+async fn unknown_issue_is_not_found() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+    let resp = app
+        .get_delivery_status(&format!("/issues/{}", Uuid::new_v4()))
+        .await;
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn delivered_issue_is_logged() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let id = app.publish_issue("foo", "bar").await;
+    assert_eq!(get_counts(&app, id).await, [2, 0, 0, 0, 0]);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+    assert_eq!(get_counts(&app, id).await, [0, 0, 2, 0, 0]);
+
+    let rows = sqlx::query!("SELECT status, n_attempts FROM issue_deliveries")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().all(|r| r.status == "sent" && r.n_attempts == 1));
+
+    let html = app.get_delivery_status("").await.text().await.unwrap();
+    assert!(html.contains(&format!(
+        r#"<a href="/admin/deliveries/issues/{id}">foo</a>"#
+    )));
+    assert!(html.contains("done"));
+}
+
+#[tokio::test]
+async fn retrying_and_failed_deliveries_are_counted() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let id = app.publish_issue("foo", "bar").await;
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+    drop(mock);
+    assert_eq!(get_counts(&app, id).await, [0, 2, 0, 0, 0]);
+    let html = app
+        .get_delivery_status(&format!("/issues/{id}"))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("0% done, 2 pending"));
+
+    // exhaust the retries of one delivery
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = 10, execute_after = now()
+        WHERE subscriber_email = (SELECT min(subscriber_email) FROM issue_delivery_queue)
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+    drop(mock);
+    assert_eq!(get_counts(&app, id).await, [0, 1, 0, 0, 1]);
+
+    // retrying a dead letter requeues it
+    let row = sqlx::query!("SELECT newsletter_issue_id, subscriber_email FROM failed_deliveries")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    app.post_failed_delivery(
+        "retry",
+        &serde_json::json!({
+            "newsletter_issue_id": row.newsletter_issue_id,
+            "subscriber_email": row.subscriber_email,
+        }),
+    )
+    .await;
+    assert_eq!(get_counts(&app, id).await, [1, 1, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn invalid_email_is_skipped() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    let id = app.publish_issue("foo", "bar").await;
+
+    sqlx::query!("UPDATE subscriptions SET email = 'not an email'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+    assert_eq!(get_counts(&app, id).await, [0, 0, 0, 1, 0]);
+}
+
+#[tokio::test]
+async fn deliveries_to_subscriber_are_listed() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    let email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .email;
+    app.publish_issue("foo", "bar").await;
+
+    let path = format!("/subscriber?email={}", urlencoding::encode(&email));
+    let html = app.get_delivery_status(&path).await.text().await.unwrap();
+    assert!(html.contains("1 deliveries to"));
+    assert!(html.contains("<td>queued</td>"));
+
+    Mock::given(any())
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({"MessageID": "msg-1"})),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+
+    let row =
+        sqlx::query!("SELECT provider_message_id, enqueued_at, finished_at FROM issue_deliveries")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+    assert_eq!(row.provider_message_id.as_deref(), Some("msg-1"));
+    assert!(row.enqueued_at <= row.finished_at);
+
+    let html = app.get_delivery_status(&path).await.text().await.unwrap();
+    assert!(html.contains("1 deliveries to"));
+    assert!(html.contains("<td>sent</td>"));
+    assert!(html.contains("<td>msg-1</td>"));
+
+    let html = app
+        .get_delivery_status("/subscriber?email=nobody%40example.com")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("0 deliveries to"));
+}
+
+/// A recipient rejected by the provider is not retried; the subscriber is
+/// marked as bounced, and left out of later issues
+#[tokio::test]
+async fn rejected_recipient_is_bounced() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    let id = app.publish_issue("foo", "bar").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive.",
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+
+    assert_eq!(get_counts(&app, id).await, [0, 0, 0, 0, 0]);
+    let html = app
+        .get_delivery_status(&format!("/issues/{id}"))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains(r#"<td id="bounced">1</td>"#));
+    assert!(html.contains("done"));
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "bounced");
+
+    // nothing is enqueued for the next issue
+    app.post_newsletters(&serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    let queued = sqlx::query!("SELECT count(*) AS n FROM issue_delivery_queue")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.n, Some(0));
+}
@@ -0,0 +1,193 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+use zero_to_prod::delivery::DeliveryWorker;
+use zero_to_prod::email_client::EmailProvider;
+use zero_to_prod::email_client::RateLimitedClient;
+use zero_to_prod::shutdown;
+
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+/// Publish an issue to all confirmed subscribers, then run a `DeliveryWorker`
+/// with `workers` tasks until the queue is empty. Returns the time taken.
+async fn deliver_issue(
+    app: &TestApp,
+    email_client: Arc<dyn EmailProvider>,
+    workers: usize,
+) -> Duration {
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&contents).await;
+
+    let worker = DeliveryWorker::new(
+        app.pool.clone(),
+        email_client,
+        app.base_url.clone(),
+        app.hmac_secret.clone(),
+    );
+    let (_trigger, shutdown) = shutdown::channel();
+    let start = Instant::now();
+    let handle = tokio::spawn(worker.run(workers, shutdown));
+    while app.queue_len().await > 0 {
+        assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let elapsed = start.elapsed();
+    handle.abort();
+    elapsed
+}
+
+#[tokio::test]
+async fn deliveries_are_sent_concurrently() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    for _ in 0..4 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(4)
+        .mount(&app.email_server)
+        .await;
+
+    let elapsed = deliver_issue(&app, app.email_client.clone(), 4).await;
+    // sequentially, this would take at least 2 s
+    assert!(elapsed < Duration::from_millis(1500), "{elapsed:?}");
+}
+
+#[tokio::test]
+async fn sends_are_rate_limited_across_workers() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    for _ in 0..4 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(4)
+        .mount(&app.email_server)
+        .await;
+
+    // like `EmailClientSettings::client` with `max_per_second: 2`
+    let email_client = Arc::new(RateLimitedClient::new(
+        app.email_client.clone(),
+        NonZeroU32::new(2),
+        None,
+    ));
+    let elapsed = deliver_issue(&app, email_client, 4).await;
+    // a burst of 2, then one send every 500 ms
+    assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
+}
+
+/// An idle worker picks up a new issue as soon as it is published, rather than
+/// on its next poll
+#[tokio::test]
+async fn idle_worker_is_woken_up_by_new_issue() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let worker = DeliveryWorker::new(
+        app.pool.clone(),
+        app.email_client.clone(),
+        app.base_url.clone(),
+        app.hmac_secret.clone(),
+    );
+    let (_trigger, shutdown) = shutdown::channel();
+    let handle = tokio::spawn(worker.run(2, shutdown));
+    // let the worker find the queue empty, and start listening
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&contents).await;
+
+    let start = Instant::now();
+    while app.queue_len().await > 0 {
+        // well below the polling interval
+        assert!(start.elapsed() < Duration::from_secs(3), "timed out");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    handle.abort();
+}
+
+/// On shutdown, a delivery that is being sent is allowed to finish, after
+/// which the worker stops without starting the next one
+#[tokio::test]
+async fn shutdown_waits_for_in_flight_delivery() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    for _ in 0..2 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&contents).await;
+
+    let worker = DeliveryWorker::new(
+        app.pool.clone(),
+        app.email_client.clone(),
+        app.base_url.clone(),
+        app.hmac_secret.clone(),
+    );
+    let (trigger, shutdown) = shutdown::channel();
+    let handle = tokio::spawn(worker.run(1, shutdown));
+
+    // mid-send
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    trigger.trigger();
+
+    tokio::time::timeout(Duration::from_secs(2), handle)
+        .await
+        .expect("worker did not stop")
+        .unwrap()
+        .unwrap();
+
+    // the first delivery was completed, the second was never started
+    assert_eq!(app.queue_len().await, 1);
+    let sent = sqlx::query!("SELECT count(*) AS n FROM issue_deliveries WHERE status = 'sent'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(sent.n, Some(1));
+}
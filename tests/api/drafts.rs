@@ -0,0 +1,205 @@
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::check_redirect;
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+/// Returns the id of the new draft
+async fn create_draft(
+    app: &TestApp,
+    content: &str,
+) -> Uuid {
+    let body = serde_json::json!({
+        "title": "My draft",
+        "content": content,
+    });
+    let resp = app.post_draft("", &body).await;
+    assert_eq!(resp.status().as_u16(), 303);
+
+    let id =
+        sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues WHERE status = 'draft'")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+    check_redirect(&resp, &format!("/admin/newsletters/drafts/{id}"));
+    id
+}
+
+async fn issue_status(
+    app: &TestApp,
+    id: Uuid,
+) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn drafts_require_login() {
+    let app = spawn_app().await;
+    let id = Uuid::new_v4();
+
+    let resp = app.get_draft("").await;
+    check_redirect(&resp, "/login");
+    let resp = app.get_draft(&format!("/{id}")).await;
+    check_redirect(&resp, "/login");
+
+    let body = serde_json::json!({"title": "foo", "content": "bar"});
+    let resp = app.post_draft("", &body).await;
+    check_redirect(&resp, "/login");
+}
+
+#[tokio::test]
+async fn draft_can_be_edited_and_previewed() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    let id = create_draft(&app, "# Hello").await;
+
+    // drafts are never delivered
+    assert_eq!(app.queue_len().await, 0);
+
+    let html = app.get_draft(&format!("/{id}")).await.text().await.unwrap();
+    assert!(html.contains("Draft has been saved."));
+    assert!(html.contains("# Hello</textarea>"));
+
+    let html = app.get_drafts_html().await;
+    assert!(html.contains("1 drafts"));
+    assert!(html.contains("My draft"));
+
+    let body = serde_json::json!({
+        "title": "My <edited> draft",
+        "content": "Hello, **{{ name }}**",
+    });
+    let resp = app.post_draft(&format!("/{id}"), &body).await;
+    check_redirect(&resp, &format!("/admin/newsletters/drafts/{id}"));
+
+    let html = app.get_draft(&format!("/{id}")).await.text().await.unwrap();
+    assert!(html.contains("edited"));
+    assert!(!html.contains("<edited>"));
+    assert!(html.contains("Hello, **{{ name }}**</textarea>"));
+
+    let resp = app.get_draft(&format!("/{id}/preview")).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("<strong>"));
+    assert!(!html.contains("{{ name }}"));
+
+    assert_eq!(app.queue_len().await, 0);
+}
+
+#[tokio::test]
+async fn missing_draft_is_not_found() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let id = Uuid::new_v4();
+    let resp = app.get_draft(&format!("/{id}")).await;
+    assert_eq!(resp.status().as_u16(), 404);
+    let resp = app.get_draft(&format!("/{id}/preview")).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    let body = serde_json::json!({"title": "foo", "content": "bar"});
+    let resp = app.post_draft(&format!("/{id}"), &body).await;
+    check_redirect(&resp, "/admin/newsletters/drafts");
+    assert!(app.get_drafts_html().await.contains("No such draft."));
+}
+
+#[tokio::test]
+async fn draft_can_be_deleted() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let id = create_draft(&app, "bar").await;
+    let resp = app
+        .post_draft(&format!("/{id}/delete"), &serde_json::json!({}))
+        .await;
+    check_redirect(&resp, "/admin/newsletters/drafts");
+
+    let html = app.get_drafts_html().await;
+    assert!(html.contains("Draft has been deleted."));
+    assert!(html.contains("0 drafts"));
+}
+
+#[tokio::test]
+async fn published_draft_is_delivered_once() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    let id = create_draft(&app, "bar").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = serde_json::json!({
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "send_at": "",
+    });
+    let resp = app.post_draft(&format!("/{id}/publish"), &body).await;
+    check_redirect(&resp, "/admin/newsletters/drafts");
+    let html = app.get_drafts_html().await;
+    assert!(html.contains("New issue is being published..."));
+    assert!(html.contains("0 drafts"));
+    assert_eq!(issue_status(&app, id).await, "published");
+
+    // resubmitting the same form
+    let resp = app.post_draft(&format!("/{id}/publish"), &body).await;
+    check_redirect(&resp, "/admin/newsletters/drafts");
+    assert!(app
+        .get_drafts_html()
+        .await
+        .contains("Issue has already been published."));
+
+    // published issues can no longer be edited
+    let resp = app.get_draft(&format!("/{id}")).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    assert_eq!(app.queue_len().await, 1);
+    app.send_all_emails().await;
+}
+
+#[tokio::test]
+async fn invalid_draft_is_not_published() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    let id = create_draft(&app, "Hello {{ name").await;
+
+    let key = Uuid::new_v4().to_string();
+    let body = serde_json::json!({"idempotency_key": key});
+    let resp = app.post_draft(&format!("/{id}/publish"), &body).await;
+    check_redirect(&resp, &format!("/admin/newsletters/drafts/{id}"));
+    let html = app.get_draft(&format!("/{id}")).await.text().await.unwrap();
+    assert!(html.contains("Invalid template"));
+    assert_eq!(issue_status(&app, id).await, "draft");
+    assert_eq!(app.queue_len().await, 0);
+
+    // the key was not consumed, so the fixed draft can be published with it
+    let body = serde_json::json!({"title": "My draft", "content": "Hello {{ name }}"});
+    app.post_draft(&format!("/{id}"), &body).await;
+    let body = serde_json::json!({"idempotency_key": key});
+    app.post_draft(&format!("/{id}/publish"), &body).await;
+    assert_eq!(issue_status(&app, id).await, "published");
+    assert_eq!(app.queue_len().await, 1);
+}
@@ -0,0 +1,205 @@
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::check_redirect;
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+/// Publish an issue to one confirmed subscriber, and fail to deliver it on the
+/// last allowed attempt. Returns the form data identifying the failed delivery.
+async fn dead_letter_issue(app: &TestApp) -> serde_json::Value {
+    create_confirmed_subscriber(app).await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&contents).await;
+
+    // skip the backoff
+    sqlx::query!("UPDATE issue_delivery_queue SET n_retries = 10")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+    drop(mock);
+
+    let row = sqlx::query!("SELECT newsletter_issue_id, subscriber_email FROM failed_deliveries")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    serde_json::json!({
+        "newsletter_issue_id": row.newsletter_issue_id,
+        "subscriber_email": row.subscriber_email,
+    })
+}
+
+#[tokio::test]
+async fn failed_deliveries_requires_login() {
+    let app = spawn_app().await;
+    let resp = app
+        .api_client
+        .get(format!("{}/admin/deliveries/failed", app.addr))
+        .send()
+        .await
+        .unwrap();
+    check_redirect(&resp, "/login");
+
+    let resp = app
+        .post_failed_delivery(
+            "retry",
+            &serde_json::json!({
+                "newsletter_issue_id": Uuid::new_v4(),
+                "subscriber_email": "foo@bar.com",
+            }),
+        )
+        .await;
+    check_redirect(&resp, "/login");
+}
+
+#[tokio::test]
+async fn exhausted_delivery_is_dead_lettered() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let form = dead_letter_issue(&app).await;
+
+    // no longer retried
+    assert_eq!(app.queue_len().await, 0);
+    let row = sqlx::query!("SELECT n_attempts, last_error FROM failed_deliveries")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(row.n_attempts, 11);
+    assert!(row.last_error.contains("500"));
+
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains("1 failed deliveries"));
+    assert!(html.contains(form["subscriber_email"].as_str().unwrap()));
+}
+
+#[tokio::test]
+async fn retry_failed_delivery() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let form = dead_letter_issue(&app).await;
+
+    let resp = app.post_failed_delivery("retry", &form).await;
+    check_redirect(&resp, "/admin/deliveries/failed");
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains("has been requeued"));
+    assert!(html.contains("0 failed deliveries"));
+
+    let task = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 0);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+    assert_eq!(app.queue_len().await, 0);
+
+    // already retried
+    let resp = app.post_failed_delivery("retry", &form).await;
+    check_redirect(&resp, "/admin/deliveries/failed");
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains("No such failed delivery"));
+}
+
+#[tokio::test]
+async fn discard_failed_delivery() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let form = dead_letter_issue(&app).await;
+
+    let resp = app.post_failed_delivery("discard", &form).await;
+    check_redirect(&resp, "/admin/deliveries/failed");
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains("has been discarded"));
+    assert!(html.contains("0 failed deliveries"));
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+    assert_eq!(app.queue_len().await, 0);
+}
+
+/// The failed delivery is kept, so that there is still a record of it
+#[tokio::test]
+async fn retry_to_unsubscribed_subscriber_keeps_failed_delivery() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let form = dead_letter_issue(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let resp = app.post_failed_delivery("retry", &form).await;
+    check_redirect(&resp, "/admin/deliveries/failed");
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains("is no longer confirmed"));
+    assert!(html.contains("1 failed deliveries"));
+
+    assert_eq!(app.queue_len().await, 0);
+    let logged = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(logged.status, "failed");
+}
+
+/// Nothing is inserted if the delivery is already back in the queue, but the
+/// subscriber is still confirmed, so that is not an error
+#[tokio::test]
+async fn retry_already_queued_delivery() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let form = dead_letter_issue(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue
+            (newsletter_issue_id, subscriber_email, subscriber_id)
+        SELECT newsletter_issue_id, subscriber_email, subscriber_id
+        FROM issue_deliveries
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let resp = app.post_failed_delivery("retry", &form).await;
+    check_redirect(&resp, "/admin/deliveries/failed");
+    let html = app.get_failed_deliveries_html().await;
+    assert!(html.contains("was already queued"));
+    assert!(!html.contains("is no longer confirmed"));
+    assert!(html.contains("0 failed deliveries"));
+    assert_eq!(app.queue_len().await, 1);
+}
@@ -0,0 +1,93 @@
+use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+async fn get_feed(
+    app: &TestApp,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> reqwest::Response {
+    let mut req = app.api_client.get(format!("{}{path}", app.addr));
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    req.send().await.unwrap()
+}
+
+#[tokio::test]
+async fn feeds_contain_published_issues() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    app.publish_issue("Fish & <chips>", "Hi **{{ name }}** & co")
+        .await;
+    let id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    for (path, content_type) in [
+        ("/feed.xml", "application/atom+xml"),
+        ("/rss.xml", "application/rss+xml"),
+    ] {
+        let resp = get_feed(&app, path, &[]).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(resp.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with(content_type));
+        let xml = resp.text().await.unwrap();
+        assert!(xml.contains("Fish &amp; &lt;chips&gt;"), "{xml}");
+        assert!(xml.contains(&format!("urn:uuid:{id}")));
+        assert!(xml.contains(&format!("/issues/{id}")));
+        assert!(xml.contains("&lt;strong&gt;reader&lt;/strong&gt; &amp;amp; co"));
+    }
+}
+
+#[tokio::test]
+async fn unchanged_feed_is_not_modified() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    app.publish_issue("foo", "Hi **{{ name }}** & co").await;
+
+    for path in ["/feed.xml", "/rss.xml"] {
+        let resp = get_feed(&app, path, &[]).await;
+        let etag = resp.headers()["ETag"].to_str().unwrap().to_owned();
+        let last_modified = resp.headers()["Last-Modified"].to_str().unwrap().to_owned();
+
+        let resp = get_feed(&app, path, &[("If-None-Match", &etag)]).await;
+        assert_eq!(resp.status().as_u16(), 304);
+        assert_eq!(resp.headers()["ETag"].to_str().unwrap(), etag);
+        assert!(resp.text().await.unwrap().is_empty());
+
+        let resp = get_feed(&app, path, &[("If-Modified-Since", &last_modified)]).await;
+        assert_eq!(resp.status().as_u16(), 304);
+    }
+
+    // a new issue invalidates the etag
+    let resp = get_feed(&app, "/feed.xml", &[]).await;
+    let etag = resp.headers()["ETag"].to_str().unwrap().to_owned();
+    app.publish_issue("bar", "Hi **{{ name }}** & co").await;
+    let resp = get_feed(&app, "/feed.xml", &[("If-None-Match", &etag)]).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_ne!(resp.headers()["ETag"].to_str().unwrap(), etag);
+}
+
+#[tokio::test]
+async fn drafts_are_not_in_feeds() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let body = serde_json::json!({"title": "Secret draft", "content": "bar"});
+    app.post_draft("", &body).await;
+
+    for path in ["/feed.xml", "/rss.xml"] {
+        let resp = get_feed(&app, path, &[]).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(!resp.text().await.unwrap().contains("Secret draft"));
+    }
+}
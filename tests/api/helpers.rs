@@ -1,6 +1,11 @@
+use std::sync::Arc;
+
 use argon2::password_hash::SaltString;
 use argon2::Argon2;
 use argon2::PasswordHasher;
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use linkify::Link;
 use linkify::LinkFinder;
 use linkify::LinkKind;
@@ -15,14 +20,21 @@ use sqlx::Executor;
 use sqlx::PgConnection;
 use sqlx::PgPool;
 use uuid::Uuid;
+use wiremock::matchers::method;
+use wiremock::matchers::path;
+use wiremock::Mock;
 use wiremock::MockServer;
+use wiremock::ResponseTemplate;
 use zero_to_prod::configuration::get_configuration;
 use zero_to_prod::configuration::DatabaseSettings;
 use zero_to_prod::delivery::try_send_email;
 use zero_to_prod::delivery::DeliveryOutcome;
-use zero_to_prod::email_client::EmailClient;
+use zero_to_prod::email_client::EmailProvider;
+use zero_to_prod::scheduler::try_publish_scheduled_issue;
+use zero_to_prod::scheduler::SchedulerOutcome;
 use zero_to_prod::startup::get_connection_pool;
 use zero_to_prod::startup::Application;
+use zero_to_prod::startup::HmacSecret;
 use zero_to_prod::telemetry::get_subscriber;
 use zero_to_prod::telemetry::init_subscriber;
 
@@ -164,7 +176,10 @@ pub struct TestApp {
     /// A persistent `Client` used to persist cookies across more than one
     /// request
     pub api_client: reqwest::Client,
-    pub email_client: EmailClient,
+    pub email_client: Arc<dyn EmailProvider>,
+    /// Used by the delivery worker to generate unsubscribe links
+    pub base_url: String,
+    pub hmac_secret: HmacSecret,
 }
 
 impl TestApp {
@@ -186,6 +201,19 @@ impl TestApp {
             .unwrap()
     }
 
+    /// `token` is taken from an (expired) confirmation link
+    pub async fn post_resend_confirmation(
+        &self,
+        token: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/confirm/resend", self.addr))
+            .form(&[("subscription_token", token)])
+            .send()
+            .await
+            .unwrap()
+    }
+
     pub async fn get_newsletters(&self) -> reqwest::Response {
         self.api_client
             .get(format!("{}/admin/newsletters", self.addr))
@@ -223,6 +251,63 @@ impl TestApp {
             .unwrap()
     }
 
+    /// Publish an issue to all confirmed subscribers (without delivering it),
+    /// and return its id
+    pub async fn publish_issue(
+        &self,
+        title: &str,
+        content: &str,
+    ) -> Uuid {
+        let contents = serde_json::json!({
+            "title": title,
+            "content": content,
+            "idempotency_key": Uuid::new_v4().to_string(),
+        });
+        self.post_newsletters(&contents).await;
+        sqlx::query!(
+            r#"
+            SELECT newsletter_issue_id
+            FROM newsletter_issues
+            ORDER BY published_at DESC NULLS LAST
+            LIMIT 1
+            "#
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+    }
+
+    pub async fn post_newsletters_preview<B>(
+        &self,
+        body: &B,
+    ) -> reqwest::Response
+    where
+        B: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters/preview", self.addr))
+            .form(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_newsletters_test<B>(
+        &self,
+        body: &B,
+    ) -> reqwest::Response
+    where
+        B: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters/test", self.addr))
+            .form(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
     pub async fn get_admin_dashboard(&self) -> Response {
         self.api_client
             .get(format!("{}/admin/dashboard", self.addr))
@@ -313,6 +398,143 @@ impl TestApp {
             .unwrap()
     }
 
+    pub async fn get_drafts_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/drafts", self.addr))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `path` is relative to `/admin/newsletters/drafts`, e.g. `/{id}/preview`
+    pub async fn get_draft(
+        &self,
+        path: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters/drafts{path}", self.addr))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// `path` is relative to `/admin/newsletters/drafts`, e.g. `/{id}/publish`
+    pub async fn post_draft<B>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> reqwest::Response
+    where
+        B: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters/drafts{path}", self.addr))
+            .form(body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_scheduled_issues_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/scheduled", self.addr))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `action` is either `reschedule` or `cancel`
+    pub async fn post_scheduled_issue<B>(
+        &self,
+        action: &str,
+        body: &B,
+    ) -> reqwest::Response
+    where
+        B: Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/scheduled/{action}",
+                self.addr
+            ))
+            .form(body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_welcome_email_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/welcome", self.addr))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_welcome_email<B>(
+        &self,
+        body: &B,
+    ) -> reqwest::Response
+    where
+        B: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/welcome", self.addr))
+            .form(body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// `path` is relative to `/admin/deliveries`, e.g. `/issues/{id}`
+    pub async fn get_delivery_status(
+        &self,
+        path: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/deliveries{path}", self.addr))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_failed_deliveries_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/deliveries/failed", self.addr))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `action` is either `retry` or `discard`
+    pub async fn post_failed_delivery<B>(
+        &self,
+        action: &str,
+        body: &B,
+    ) -> reqwest::Response
+    where
+        B: Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/deliveries/failed/{action}", self.addr))
+            .form(body)
+            .send()
+            .await
+            .unwrap()
+    }
+
     /// Extract text and html links from an email response (e.g. from mailchimp)
     pub fn get_confirmation_links(
         &self,
@@ -345,17 +567,69 @@ impl TestApp {
         ConfirmationLinks { text, html }
     }
 
+    /// Extract the unsubscribe link from a delivered issue. Unlike
+    /// `get_confirmation_links`, other links may be present in the body.
+    pub fn get_unsubscribe_link(
+        &self,
+        email_resp: &wiremock::Request,
+    ) -> Url {
+        let body: Value = serde_json::from_slice(&email_resp.body).unwrap();
+        let link = LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .find(|l| l.as_str().contains("/subscriptions/unsubscribe"))
+            .unwrap()
+            .as_str()
+            .to_owned();
+        let mut link = Url::parse(&link).unwrap();
+        assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
+
     pub async fn send_all_emails(&self) {
         // -> Result<(), anyhow::Error> {
         loop {
-            if let DeliveryOutcome::NoTasksLeft = try_send_email(&self.pool, &self.email_client)
-                .await
-                .unwrap()
+            if let DeliveryOutcome::NoTasksLeft = try_send_email(
+                &self.pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
         }
     }
+
+    /// Publish all scheduled issues that are due, like the scheduler worker
+    /// would
+    pub async fn publish_scheduled_issues(&self) {
+        while let SchedulerOutcome::IssuesDue =
+            try_publish_scheduled_issue(&self.pool).await.unwrap()
+        {}
+    }
+
+    /// Number of deliveries still in `issue_delivery_queue`, due or not
+    pub async fn queue_len(&self) -> i64 {
+        sqlx::query!("SELECT count(*) AS n FROM issue_delivery_queue")
+            .fetch_one(&self.pool)
+            .await
+            .unwrap()
+            .n
+            .unwrap()
+    }
+
+    /// Number of emails still in `email_outbox`, due or not
+    pub async fn outbox_len(&self) -> i64 {
+        sqlx::query!("SELECT count(*) AS n FROM email_outbox")
+            .fetch_one(&self.pool)
+            .await
+            .unwrap()
+            .n
+            .unwrap()
+    }
 }
 
 /// Read `DatabaseSettings` and create a db with a randomised name (but with the
@@ -413,6 +687,8 @@ pub async fn spawn_app() -> TestApp {
 
         // random db name
         rand_cfg.database.database_name = Uuid::new_v4().to_string();
+        // test the templates that would be deployed, i.e. embedded ones
+        rand_cfg.application.hot_reload_templates = false;
 
         // port 0 is reserved by the OS; the server will be spawned on an address with a
         // random available port. this address/port must then be made known to clients
@@ -473,6 +749,8 @@ pub async fn spawn_app() -> TestApp {
         test_user,
         api_client,
         email_client,
+        base_url: cfg.application.base_url,
+        hmac_secret: HmacSecret(cfg.application.hmac_secret),
     };
     // add_test_user(&test_app.pool).await;
     test_app.test_user.store(&test_app.pool).await;
@@ -487,3 +765,53 @@ pub fn check_redirect(
     assert_eq!(resp.status().as_u16(), 303);
     assert_eq!(resp.headers().get("Location").unwrap(), location);
 }
+
+/// Add a subscriber to a (typically empty) db, but don't confirm
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    // let body = "name=john&email=foo%40bar.com";
+    let body = serde_urlencoded::to_string([
+        ("name", Name().fake::<String>()),
+        ("email", SafeEmail().fake()),
+    ])
+    .unwrap();
+
+    // (scoped) mocks must always be assigned and -named-!
+    let _mock = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        // with `mount_as_scoped`, this `Mock` remains local, and will not interfere with the
+        // caller's ("global") `Mock`. local assertions are also performed at the end of this
+        // function (eagerly)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body) //.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    // the confirmation email is only enqueued
+    app.send_all_emails().await;
+
+    // see `subscribe_ok_with_confirmation`
+    let email_reqs = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(&email_reqs)
+}
+
+/// Simulate `/subscriptions/confirm`
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
@@ -0,0 +1,129 @@
+use uuid::Uuid;
+
+use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+async fn get_html(
+    app: &TestApp,
+    path: &str,
+) -> (u16, String) {
+    let resp = app
+        .api_client
+        .get(format!("{}{path}", app.addr))
+        .send()
+        .await
+        .unwrap();
+    (resp.status().as_u16(), resp.text().await.unwrap())
+}
+
+#[tokio::test]
+async fn published_issue_is_public() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let id = app
+        .publish_issue("First <issue>", "Hi **{{ name }}**")
+        .await;
+    app.post_logout().await;
+
+    let (status, html) = get_html(&app, "/issues").await;
+    assert_eq!(status, 200);
+    assert!(html.contains(&format!(
+        r#"<a href="/issues/{id}">First &lt;issue&gt;</a>"#
+    )));
+
+    let (status, html) = get_html(&app, &format!("/issues/{id}")).await;
+    assert_eq!(status, 200);
+    assert!(html.contains("<h1>First &lt;issue&gt;</h1>"));
+    // no recipient, so placeholders get public values
+    assert!(html.contains("Hi <strong>reader</strong>"));
+    assert!(!html.contains("{{"));
+}
+
+#[tokio::test]
+async fn unpublished_issues_are_not_public() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let body = serde_json::json!({"title": "Secret draft", "content": "bar"});
+    app.post_draft("", &body).await;
+    let id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    let (_, html) = get_html(&app, "/issues").await;
+    assert!(html.contains("No issues yet."));
+    assert!(!html.contains("Secret draft"));
+    let (_, html) = get_html(&app, "/").await;
+    assert!(!html.contains("Secret draft"));
+
+    let (status, _) = get_html(&app, &format!("/issues/{id}")).await;
+    assert_eq!(status, 404);
+    let (status, _) = get_html(&app, &format!("/issues/{}", Uuid::new_v4())).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn issues_are_paged() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    for i in 1..=12 {
+        app.publish_issue(&format!("Issue #{i:02}"), "bar").await;
+    }
+
+    let (_, html) = get_html(&app, "/issues").await;
+    // most recent first
+    assert!(html.contains("Issue #12"));
+    assert!(html.contains("Issue #03"));
+    assert!(!html.contains("Issue #02"));
+    assert!(html.find("Issue #12") < html.find("Issue #11"));
+    assert!(html.contains(r#"href="/issues?page=2""#));
+    assert!(!html.contains("Newer issues"));
+
+    let (_, html) = get_html(&app, "/issues?page=2").await;
+    assert!(html.contains("Issue #02"));
+    assert!(html.contains("Issue #01"));
+    assert!(!html.contains("Issue #03"));
+    assert!(html.contains(r#"href="/issues?page=1""#));
+    assert!(!html.contains("Older issues"));
+
+    let (status, html) = get_html(&app, "/issues?page=3").await;
+    assert_eq!(status, 200);
+    assert!(html.contains("No issues yet."));
+}
+
+#[tokio::test]
+async fn out_of_range_page_is_not_found() {
+    let app = spawn_app().await;
+    let (status, _) = get_html(&app, &format!("/issues?page={}", i64::MAX)).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn home_shows_latest_issues_and_subscribe_form() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let (status, html) = get_html(&app, "/").await;
+    assert_eq!(status, 200);
+    assert!(html.contains("No issues yet."));
+
+    for i in 1..=6 {
+        app.publish_issue(&format!("Issue #{i}"), "bar").await;
+    }
+
+    let (_, html) = get_html(&app, "/").await;
+    assert!(html.contains("Issue #6"));
+    assert!(html.contains("Issue #2"));
+    assert!(!html.contains("Issue #1<"));
+    assert!(html.contains(r#"<a href="/issues">All issues</a>"#));
+    assert!(html.contains(r#"<form action="/subscriptions" method="post">"#));
+    assert!(html.contains(r#"name="email""#));
+}
@@ -1,11 +1,22 @@
 // fn main not required
 mod change_password;
+mod delivery_status;
+mod delivery_worker;
+mod drafts;
+mod failed_deliveries;
+mod feeds;
 mod health_check;
 mod helpers;
+mod issues;
 mod login;
 mod newsletters;
+mod scheduled_issues;
+mod smtp;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod test_issues;
+mod welcome;
 
 // 'no external crate' -- add to Cargo.toml:
 // [lib]
@@ -1,8 +1,5 @@
 use std::time::Duration;
 
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
 use wiremock::matchers::any;
 use wiremock::matchers::method;
 use wiremock::matchers::path;
@@ -10,9 +7,9 @@ use wiremock::Mock;
 use wiremock::ResponseTemplate;
 
 use crate::helpers::check_redirect;
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::create_unconfirmed_subscriber;
 use crate::helpers::spawn_app;
-use crate::helpers::ConfirmationLinks;
-use crate::helpers::TestApp;
 
 // we are no longer concerned with validating the structure of Newsletter
 // because we now expect data to be provided via html form
@@ -60,54 +57,6 @@ async fn not_logged_in() {
     // we don't test POST because if GET requires creds, then so will POST
 }
 
-/// Add a subscriber to a (typically empty) db, but don't confirm
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    // let body = "name=john&email=foo%40bar.com";
-    let body = serde_urlencoded::to_string([
-        ("name", Name().fake::<String>()),
-        ("email", SafeEmail().fake()),
-    ])
-    .unwrap();
-
-    // (scoped) mocks must always be assigned and -named-!
-    let _mock = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        // with `mount_as_scoped`, this `Mock` remains local, and will not interfere with the
-        // caller's ("global") `Mock`. local assertions are also performed at the end of this
-        // function (eagerly)
-        .mount_as_scoped(&app.email_server)
-        .await;
-
-    app.post_subscriptions(body) //.into())
-        .await
-        .error_for_status()
-        .unwrap();
-
-    // see `subscribe_ok_with_confirmation`
-    let email_reqs = app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-
-    app.get_confirmation_links(&email_reqs)
-}
-
-/// Simulate `/subscriptions/confirm`
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
-
 #[tokio::test]
 async fn no_confirmed_subscribers() {
     let app = spawn_app().await;
@@ -309,3 +258,323 @@ async fn concurrent() {
 //
 //     app.send_all_emails().await;
 // }
+
+/// A failed delivery should be postponed (not retried in place), so that the
+/// worker can move on to other recipients
+#[tokio::test]
+async fn transient_error_is_retried_later() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&contents).await;
+
+    // returns immediately; the failed task is not yet due
+    app.send_all_emails().await;
+
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS postponed FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert_eq!(task.postponed, Some(true));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .named("Retrying delivery")
+        .mount(&app.email_server)
+        .await;
+
+    // fast-forward
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    app.send_all_emails().await;
+
+    let remaining = sqlx::query!("SELECT count(*) AS n FROM issue_delivery_queue")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.n, Some(0));
+}
+
+/// A configuration error (e.g. a bad API token) would fail for every
+/// recipient; the delivery is paused, but doesn't count towards its retries
+/// (and is never dead-lettered)
+#[tokio::test]
+async fn misconfigured_delivery_is_postponed_without_retry() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+            "ErrorCode": 10,
+            "Message": "Bad or missing API token",
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&contents).await;
+
+    // the email client is now paused, so don't send anything else
+    app.send_all_emails().await;
+
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS postponed FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 0);
+    assert_eq!(task.postponed, Some(true));
+
+    let failed = sqlx::query!("SELECT count(*) AS n FROM failed_deliveries")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.n, Some(0));
+}
+
+/// Throttling by the provider (`429`) pauses the delivery, but doesn't count
+/// towards its retries
+#[tokio::test]
+async fn rate_limited_delivery_is_postponed_without_retry() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&contents).await;
+
+    app.send_all_emails().await;
+
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS postponed FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 0);
+    assert_eq!(task.postponed, Some(true));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .named("Delivery after pause")
+        .mount(&app.email_server)
+        .await;
+
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    app.send_all_emails().await;
+
+    let delivery = sqlx::query!("SELECT status, n_attempts FROM issue_deliveries")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(delivery.n_attempts, 1);
+}
+
+/// Content is written in Markdown, and delivered as both HTML and plain text
+#[tokio::test]
+async fn markdown_is_rendered() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "# Hello\n\nSome *emphasis* and a [link](https://foo.com).\n\n<script>alert(1)</script>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // the preview shows exactly what will be sent, but doesn't send it
+    let html = app
+        .post_newsletters_preview(&contents)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<h1>Hello</h1>"));
+    assert!(html.contains("Some emphasis and a link (https://foo.com)."));
+    assert!(!html.contains("<script>"));
+    let issues = sqlx::query!("SELECT count(*) AS n FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.n, Some(0));
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(&contents).await;
+    app.send_all_emails().await;
+
+    let req = mock.received_requests().await.pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(html_body.contains("<h1>Hello</h1>"));
+    assert!(html_body.contains("<em>emphasis</em>"));
+    assert!(!html_body.contains("<script>"));
+    assert!(text_body.starts_with("Hello\n\nSome emphasis and a link (https://foo.com)."));
+    assert!(!text_body.contains('*'));
+
+    // the source is kept
+    let issue = sqlx::query!("SELECT content FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert!(issue.content.starts_with("# Hello"));
+}
+
+/// Placeholders are filled in per subscriber
+#[tokio::test]
+async fn template_placeholders_are_rendered_per_subscriber() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "Hi {{ name }}!\n\n[Leave]({{ unsubscribe_url }})",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&contents).await;
+    app.send_all_emails().await;
+
+    let names: Vec<String> = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.name)
+        .collect();
+    for req in mock.received_requests().await {
+        let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+        let text_body = body["TextBody"].as_str().unwrap();
+        assert!(
+            names
+                .iter()
+                .any(|name| text_body.starts_with(&format!("Hi {name}!"))),
+            "{text_body}"
+        );
+
+        let link = app.get_unsubscribe_link(&req);
+        let html_body = body["HtmlBody"].as_str().unwrap();
+        assert!(html_body.contains(&format!(
+            r#"<a href="{}" rel="noopener noreferrer">Leave</a>"#,
+            htmlescape::encode_minimal(&link.as_str().replace(&format!(":{}", app.port), ""))
+        )));
+    }
+}
+
+/// Invalid templates are rejected before anything is stored, and the same
+/// idempotency key can be reused once the template is fixed
+#[tokio::test]
+async fn invalid_template_is_rejected() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    let key = uuid::Uuid::new_v4().to_string();
+    for content in ["Hi {{ name", "Hi {{ email }}"] {
+        let contents = serde_json::json!({
+            "title": "foo",
+            "content": content,
+            "idempotency_key": key,
+        });
+        let resp = app.post_newsletters(&contents).await;
+        check_redirect(&resp, "/admin/newsletters");
+        assert!(app
+            .get_newsletters_html()
+            .await
+            .contains("Invalid template"));
+    }
+
+    let issues = sqlx::query!("SELECT count(*) AS n FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.n, Some(0));
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "Hi {{ name }}",
+        "idempotency_key": key,
+    });
+    app.post_newsletters(&contents).await;
+    assert!(app
+        .get_newsletters_html()
+        .await
+        .contains("New issue is being published..."));
+    app.send_all_emails().await;
+}
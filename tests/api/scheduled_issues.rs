@@ -0,0 +1,179 @@
+use chrono::Duration;
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::check_redirect;
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+/// Value of a `datetime-local` input
+fn datetime_local(days_from_now: i64) -> String {
+    (Utc::now() + Duration::days(days_from_now))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string()
+}
+
+/// Returns the id of the scheduled issue
+async fn schedule_issue(
+    app: &TestApp,
+    send_at: &str,
+) -> Uuid {
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "send_at": send_at,
+    });
+    let resp = app.post_newsletters(&contents).await;
+    check_redirect(&resp, "/admin/newsletters");
+    assert!(app
+        .get_newsletters_html()
+        .await
+        .contains("Issue has been scheduled for"));
+
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues WHERE status = 'scheduled'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+/// Pretend that the issue is due
+async fn fast_forward(app: &TestApp) {
+    sqlx::query!("UPDATE newsletter_issues SET send_at = now() - interval '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_issue_is_published_when_due() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    schedule_issue(&app, &datetime_local(1)).await;
+
+    // not yet due
+    app.publish_scheduled_issues().await;
+    assert_eq!(app.queue_len().await, 0);
+
+    fast_forward(&app).await;
+    app.publish_scheduled_issues().await;
+    assert_eq!(app.queue_len().await, 1);
+    let issue = sqlx::query!("SELECT status, published_at FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "published");
+    assert!(issue.published_at.is_some());
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+
+    // published only once
+    app.publish_scheduled_issues().await;
+    assert_eq!(app.queue_len().await, 0);
+}
+
+#[tokio::test]
+async fn send_at_in_the_past_is_rejected() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    for send_at in [datetime_local(-1), "foo".to_string()] {
+        let contents = serde_json::json!({
+            "title": "foo",
+            "content": "bar",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "send_at": send_at,
+        });
+        let resp = app.post_newsletters(&contents).await;
+        check_redirect(&resp, "/admin/newsletters");
+    }
+
+    let issues = sqlx::query!("SELECT count(*) AS n FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.n, Some(0));
+}
+
+#[tokio::test]
+async fn reschedule_issue() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let id = schedule_issue(&app, &datetime_local(1)).await;
+    let html = app.get_scheduled_issues_html().await;
+    assert!(html.contains("1 scheduled issues"));
+    assert!(html.contains(&datetime_local(1)));
+
+    let resp = app
+        .post_scheduled_issue(
+            "reschedule",
+            &serde_json::json!({
+                "newsletter_issue_id": id,
+                "send_at": datetime_local(2),
+            }),
+        )
+        .await;
+    check_redirect(&resp, "/admin/newsletters/scheduled");
+    let html = app.get_scheduled_issues_html().await;
+    assert!(html.contains("Issue has been rescheduled for"));
+    assert!(html.contains(&datetime_local(2)));
+
+    // can't reschedule into the past
+    app.post_scheduled_issue(
+        "reschedule",
+        &serde_json::json!({
+            "newsletter_issue_id": id,
+            "send_at": datetime_local(-1),
+        }),
+    )
+    .await;
+    let html = app.get_scheduled_issues_html().await;
+    assert!(html.contains("Send time must be in the future."));
+    assert!(html.contains(&datetime_local(2)));
+}
+
+#[tokio::test]
+async fn cancel_issue() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    let id = schedule_issue(&app, &datetime_local(1)).await;
+
+    let resp = app
+        .post_scheduled_issue("cancel", &serde_json::json!({ "newsletter_issue_id": id }))
+        .await;
+    check_redirect(&resp, "/admin/newsletters/scheduled");
+    let html = app.get_scheduled_issues_html().await;
+    assert!(html.contains("Issue has been cancelled."));
+    assert!(html.contains("0 scheduled issues"));
+
+    fast_forward(&app).await;
+    app.publish_scheduled_issues().await;
+    assert_eq!(app.queue_len().await, 0);
+
+    // already cancelled
+    app.post_scheduled_issue("cancel", &serde_json::json!({ "newsletter_issue_id": id }))
+        .await;
+    assert!(app
+        .get_scheduled_issues_html()
+        .await
+        .contains("Issue is no longer scheduled."));
+}
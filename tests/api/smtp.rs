@@ -0,0 +1,254 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use secrecy::Secret;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+use zero_to_prod::configuration::SmtpAuthMechanism;
+use zero_to_prod::configuration::SmtpSettings;
+use zero_to_prod::configuration::SmtpTls;
+use zero_to_prod::domain::SubscriberEmail;
+use zero_to_prod::email_client::EmailProvider;
+use zero_to_prod::email_client::SendError;
+use zero_to_prod::email_client::SmtpClient;
+
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::spawn_app;
+
+/// What an `SmtpSink` has seen so far
+#[derive(Default)]
+struct SmtpSinkState {
+    /// Number of TCP connections accepted
+    connections: usize,
+    /// `(mechanism, username, password)` of every successful `AUTH`
+    logins: Vec<(String, String, String)>,
+    /// Raw `DATA` of every message, with the terminating `.` removed
+    messages: Vec<String>,
+}
+
+/// Minimal SMTP server that accepts everything (except recipients starting
+/// with `bounce`), and records what it receives.
+/// Only plaintext connections are supported, so the client must be configured
+/// with `SmtpTls::None`.
+struct SmtpSink {
+    port: u16,
+    state: Arc<Mutex<SmtpSinkState>>,
+}
+
+impl SmtpSink {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(SmtpSinkState::default()));
+
+        let state_ = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                state_.lock().unwrap().connections += 1;
+                tokio::spawn(Self::serve(stream, state_.clone()));
+            }
+        });
+
+        Self { port, state }
+    }
+
+    async fn serve(
+        stream: TcpStream,
+        state: Arc<Mutex<SmtpSinkState>>,
+    ) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let decode = |s: &str| String::from_utf8(STANDARD.decode(s.trim()).unwrap()).unwrap();
+
+        writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let upper = line.to_uppercase();
+            let reply: &[u8] = if upper.starts_with("EHLO") {
+                b"250-sink\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n"
+            } else if let Some(initial) = upper.strip_prefix("AUTH PLAIN") {
+                let creds = match initial.trim() {
+                    "" => {
+                        writer.write_all(b"334 \r\n").await.unwrap();
+                        lines.next_line().await.unwrap().unwrap()
+                    }
+                    // not `initial`, which has been uppercased
+                    _ => line["AUTH PLAIN".len()..].to_string(),
+                };
+                // authzid \0 authcid \0 passwd
+                let creds = decode(&creds);
+                let mut creds = creds.split('\0').skip(1);
+                let (user, pass) = (creds.next().unwrap(), creds.next().unwrap());
+                state.lock().unwrap().logins.push((
+                    "PLAIN".to_string(),
+                    user.to_string(),
+                    pass.to_string(),
+                ));
+                b"235 ok\r\n"
+            } else if upper.starts_with("AUTH LOGIN") {
+                writer.write_all(b"334 VXNlcm5hbWU6\r\n").await.unwrap();
+                let user = decode(&lines.next_line().await.unwrap().unwrap());
+                writer.write_all(b"334 UGFzc3dvcmQ6\r\n").await.unwrap();
+                let pass = decode(&lines.next_line().await.unwrap().unwrap());
+                state
+                    .lock()
+                    .unwrap()
+                    .logins
+                    .push(("LOGIN".to_string(), user, pass));
+                b"235 ok\r\n"
+            } else if upper.starts_with("DATA") {
+                writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                let mut message = String::new();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "." {
+                        break;
+                    }
+                    message.push_str(&line);
+                    message.push('\n');
+                }
+                state.lock().unwrap().messages.push(message);
+                b"250 queued\r\n"
+            } else if upper.starts_with("RCPT TO:<BOUNCE") {
+                b"550 5.1.1 no such user\r\n"
+            } else if upper.starts_with("QUIT") {
+                writer.write_all(b"221 bye\r\n").await.unwrap();
+                return;
+            } else {
+                // MAIL FROM, RCPT TO, RSET, NOOP
+                b"250 ok\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+    }
+
+    fn settings(
+        &self,
+        auth_mechanisms: Vec<SmtpAuthMechanism>,
+    ) -> SmtpSettings {
+        SmtpSettings {
+            host: "127.0.0.1".to_string(),
+            port: self.port,
+            tls: SmtpTls::None,
+            username: Some("user".to_string()),
+            password: Some(Secret::new("hunter2".to_string())),
+            auth_mechanisms,
+            max_connections: 4,
+            idle_timeout_secs: 60,
+        }
+    }
+}
+
+fn sender() -> SubscriberEmail { SubscriberEmail::parse("sender@foo.com".to_string()).unwrap() }
+
+#[tokio::test]
+async fn smtp_auth_mechanisms() {
+    for mechanism in [SmtpAuthMechanism::Plain, SmtpAuthMechanism::Login] {
+        let sink = SmtpSink::start().await;
+        let client = SmtpClient::new(
+            &sink.settings(vec![mechanism]),
+            sender(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let recipient = SubscriberEmail::parse("bar@baz.com".to_string()).unwrap();
+
+        client
+            .send_email(&recipient, "foo", "<p>bar</p>", "bar", &[])
+            .await
+            .unwrap();
+
+        let state = sink.state.lock().unwrap();
+        let expected = match mechanism {
+            SmtpAuthMechanism::Plain => "PLAIN",
+            SmtpAuthMechanism::Login => "LOGIN",
+        };
+        assert_eq!(
+            state.logins,
+            vec![(
+                expected.to_string(),
+                "user".to_string(),
+                "hunter2".to_string()
+            )]
+        );
+        assert_eq!(state.messages.len(), 1);
+        assert!(state.messages[0].contains("Subject: foo"));
+    }
+}
+
+#[tokio::test]
+async fn smtp_rejected_recipient_is_permanent() {
+    let sink = SmtpSink::start().await;
+    let client = SmtpClient::new(
+        &sink.settings(vec![SmtpAuthMechanism::Plain]),
+        sender(),
+        Duration::from_secs(5),
+    )
+    .unwrap();
+    let recipient = SubscriberEmail::parse("bounce@baz.com".to_string()).unwrap();
+
+    let e = client
+        .send_email(&recipient, "foo", "<p>bar</p>", "bar", &[])
+        .await
+        .unwrap_err();
+    assert!(matches!(e, SendError::PermanentRecipient(_)), "{e:?}");
+    assert!(sink.state.lock().unwrap().messages.is_empty());
+}
+
+/// A whole issue should be delivered over a single connection (and login),
+/// rather than one per recipient
+#[tokio::test]
+async fn smtp_connection_is_reused_across_deliveries() {
+    let mut app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    // confirmation emails still go through the mock server
+    for _ in 0..3 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    let sink = SmtpSink::start().await;
+    app.email_client = Arc::new(
+        SmtpClient::new(
+            &sink.settings(vec![SmtpAuthMechanism::Plain]),
+            sender(),
+            Duration::from_secs(5),
+        )
+        .unwrap(),
+    );
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .named("Postmark should not be used for delivery")
+        .mount(&app.email_server)
+        .await;
+
+    let contents = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_newsletters(&contents).await;
+    app.send_all_emails().await;
+
+    let state = sink.state.lock().unwrap();
+    assert_eq!(state.messages.len(), 3);
+    assert_eq!(state.connections, 1);
+    assert_eq!(state.logins.len(), 1);
+    for message in state.messages.iter() {
+        assert!(message.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(message.contains("Content-Type: text/plain"));
+        assert!(message.contains("Content-Type: text/html"));
+    }
+}
@@ -12,8 +12,8 @@ async fn subscribe_ok() {
     let body = "name=john&email=foo%40bar.com";
 
     // simulate sending an email; this is required because
-    // `subscriptions::subscribe` sends an email, but our test method
-    // `post_subscriptions` doesn't
+    // `subscriptions::subscribe` enqueues an email, which `send_all_emails`
+    // (i.e. the delivery worker) then sends
     Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
@@ -22,6 +22,7 @@ async fn subscribe_ok() {
         .await;
 
     let resp = app.post_subscriptions(body.to_owned()).await;
+    app.send_all_emails().await;
 
     assert_eq!(resp.status().as_u16(), 200);
     assert!(resp.status().is_success());
@@ -50,6 +51,8 @@ async fn subscribe_twice_ok() {
     println!("second sub ok");
     assert_eq!(resp.status().as_u16(), 200);
     assert!(resp.status().is_success());
+
+    app.send_all_emails().await;
 }
 
 /// Test that the new user is added to (and can be retrieved from) db
@@ -66,6 +69,7 @@ async fn subscribe_added_to_db() {
         .await;
 
     app.post_subscriptions(body.to_owned()).await;
+    app.send_all_emails().await;
 
     // now we check that the side-effect occurred (subscription added to db). in the
     // absence of a separate (GET) endpoint ('client-side'), the check can be
@@ -140,8 +144,17 @@ async fn subscribe_ok_with_confirmation() {
         .await;
 
     app.post_subscriptions(body.to_owned()).await;
+    let queued = sqlx::query!("SELECT recipient, subject FROM email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.recipient, "foo@bar.com");
+    assert_eq!(queued.subject, "Confirm your subscription");
+    app.send_all_emails().await;
 
     let email_reqs = app.email_server.received_requests().await.unwrap();
+    let sent: serde_json::Value = serde_json::from_slice(&email_reqs[0].body).unwrap();
+    assert_eq!(sent["Subject"], "Confirm your subscription");
     let links = app.get_confirmation_links(&email_reqs[0]);
 
     assert_eq!(links.text, links.html)
@@ -169,4 +182,79 @@ async fn fatal_db_error() {
     let resp = app.post_subscriptions(body.to_owned()).await;
 
     assert_eq!(resp.status(), 500);
+    // rolled back along with everything else
+    assert_eq!(app.outbox_len().await, 0);
+}
+
+/// The confirmation email is sent by the delivery worker, so the subscription
+/// succeeds even if the email provider is down; the email is retried later
+#[tokio::test]
+async fn subscribe_ok_when_email_fails() {
+    let app = spawn_app().await;
+    let body = "name=john&email=foo%40bar.com";
+
+    let mock = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let resp = app.post_subscriptions(body.to_owned()).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    app.send_all_emails().await;
+    drop(mock);
+
+    let email = sqlx::query!("SELECT n_retries FROM email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(email.n_retries, 1);
+
+    // the provider is back, and the retry is due
+    sqlx::query!("UPDATE email_outbox SET execute_after = now()")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+
+    assert_eq!(app.outbox_len().await, 0);
+    let email_reqs = app.email_server.received_requests().await.unwrap();
+    let links = app.get_confirmation_links(email_reqs.last().unwrap());
+    assert_eq!(links.text, links.html);
+}
+
+/// A configuration error (e.g. a bad API token) postpones the confirmation
+/// email without counting towards its retries, so it is still there once the
+/// settings have been fixed
+#[tokio::test]
+async fn confirmation_email_is_kept_on_config_error() {
+    let app = spawn_app().await;
+    let body = "name=john&email=foo%40bar.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+            "ErrorCode": 10,
+            "Message": "Bad or missing API token",
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.to_owned()).await;
+    // the email client is now paused, so don't send anything else
+    app.send_all_emails().await;
+
+    let email =
+        sqlx::query!("SELECT n_retries, execute_after > now() AS postponed FROM email_outbox")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+    assert_eq!(email.n_retries, 0);
+    assert_eq!(email.postponed, Some(true));
 }
@@ -2,8 +2,33 @@ use wiremock::matchers::method;
 use wiremock::matchers::path;
 use wiremock::Mock;
 use wiremock::ResponseTemplate;
+use zero_to_prod::token_cleanup::delete_stale_tokens;
 
+use crate::helpers::create_unconfirmed_subscriber;
 use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+/// Backdate every token by `hours`
+async fn age_tokens(
+    app: &TestApp,
+    hours: i32,
+) {
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = now() - make_interval(hours => $1)",
+        hours,
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+}
+
+fn token_from_link(link: &reqwest::Url) -> String {
+    link.query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned()
+}
 
 /// Test `/subscriptions/confirm` with no confirmation token
 #[tokio::test]
@@ -30,6 +55,7 @@ async fn confirm_ok() {
         .await;
 
     app.post_subscriptions(body.to_owned()).await;
+    app.send_all_emails().await;
 
     let email_reqs = app.email_server.received_requests().await.unwrap();
 
@@ -37,10 +63,19 @@ async fn confirm_ok() {
 
     let resp = reqwest::get(link.clone()).await.unwrap();
     assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers()["content-type"], "text/html; charset=utf-8");
+    assert!(resp.text().await.unwrap().contains("has been confirmed"));
 
-    // ensure user can only be confirmed once (not idempotent)
+    // the token is consumed, but clicking the link again is harmless
     let resp = reqwest::get(link.clone()).await.unwrap();
-    assert_eq!(resp.status().as_u16(), 500);
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp.text().await.unwrap().contains("already confirmed"));
+
+    let consumed = sqlx::query!("SELECT consumed_at FROM subscription_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert!(consumed.consumed_at.is_some());
 }
 
 /// Test that requesting the confirmation url modifies the user's `status` in
@@ -58,6 +93,7 @@ async fn confirm_modifies_user_status_in_db() {
         .await;
 
     app.post_subscriptions(body.to_owned()).await;
+    app.send_all_emails().await;
 
     let email_reqs = app.email_server.received_requests().await.unwrap();
 
@@ -78,3 +114,125 @@ async fn confirm_modifies_user_status_in_db() {
     assert_eq!(added.email, "foo@bar.com");
     assert_eq!(added.status, "confirmed");
 }
+
+/// An expired link does not confirm, but offers to send a new one, which does
+#[tokio::test]
+async fn expired_link_can_be_resent() {
+    let app = spawn_app().await;
+    let old_link = create_unconfirmed_subscriber(&app).await.html;
+    // default ttl is 48 h
+    age_tokens(&app, 72).await;
+
+    let resp = reqwest::get(old_link.clone()).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 410);
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("expired"));
+    assert!(html.contains(r#"action="/subscriptions/confirm/resend""#));
+
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "pending_confirmation");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_resend_confirmation(&token_from_link(&old_link))
+        .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp.text().await.unwrap().contains("new confirmation link"));
+    app.send_all_emails().await;
+
+    let email_reqs = app.email_server.received_requests().await.unwrap();
+    let new_link = app.get_confirmation_links(email_reqs.last().unwrap()).html;
+    assert_ne!(new_link, old_link);
+
+    // the old token is replaced
+    let resp = reqwest::get(old_link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 401);
+
+    let resp = reqwest::get(new_link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "confirmed");
+}
+
+/// Subscribing again after the link has expired sends a fresh link, rather than
+/// the expired one
+#[tokio::test]
+async fn subscribing_again_after_expiry_issues_new_token() {
+    let app = spawn_app().await;
+    let body = "name=john&email=foo%40bar.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.to_owned()).await;
+    app.send_all_emails().await;
+    age_tokens(&app, 72).await;
+    app.post_subscriptions(body.to_owned()).await;
+    app.send_all_emails().await;
+
+    let email_reqs = app.email_server.received_requests().await.unwrap();
+    let old_link = app.get_confirmation_links(&email_reqs[0]).html;
+    let new_link = app.get_confirmation_links(&email_reqs[1]).html;
+    assert_ne!(old_link, new_link);
+
+    let resp = reqwest::get(new_link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+/// Malformed and unknown tokens get the same page
+#[tokio::test]
+async fn invalid_token_is_rejected() {
+    let app = spawn_app().await;
+    for token in ["not-a-token", "aaaaaaaaaaaaaaaaaaaaaaaaa"] {
+        let resp = reqwest::get(format!(
+            "{}/subscriptions/confirm?subscription_token={token}",
+            app.addr
+        ))
+        .await
+        .unwrap();
+        assert_eq!(resp.status().as_u16(), 401);
+        assert!(resp.text().await.unwrap().contains("not valid"));
+    }
+}
+
+/// Only tokens older than twice the ttl are deleted
+#[tokio::test]
+async fn stale_tokens_are_cleaned_up() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    // expired, but recently enough to still show the "resend?" page
+    age_tokens(&app, 72).await;
+    create_unconfirmed_subscriber(&app).await;
+
+    let ttl = std::time::Duration::from_secs(48 * 3600);
+    assert_eq!(delete_stale_tokens(&app.pool, ttl).await.unwrap(), 0);
+
+    age_tokens(&app, 100).await;
+    create_unconfirmed_subscriber(&app).await;
+    assert_eq!(delete_stale_tokens(&app.pool, ttl).await.unwrap(), 2);
+
+    let left = sqlx::query!("SELECT count(*) AS \"n!\" FROM subscription_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(left, 1);
+}
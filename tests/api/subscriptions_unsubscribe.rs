@@ -0,0 +1,142 @@
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::spawn_app;
+
+/// Test `/subscriptions/unsubscribe` with a token that was not signed by us
+#[tokio::test]
+async fn unsubscribe_invalid_token() {
+    let app = spawn_app().await;
+    for token in ["", "foo", "deadbeef"] {
+        let url = format!(
+            "{}/subscriptions/unsubscribe?subscriber_id={}&token={token}",
+            app.addr,
+            Uuid::new_v4(),
+        );
+        let resp = reqwest::get(&url).await.unwrap();
+        assert_eq!(resp.status().as_u16(), 401, "{token:?}");
+        let resp = app.api_client.post(&url).send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 401, "{token:?}");
+    }
+}
+
+/// Every delivered issue should contain a working unsubscribe link, after
+/// which no further issues are delivered
+#[tokio::test]
+async fn unsubscribe_ok() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.publish_issue("foo", "bar").await;
+    app.send_all_emails().await;
+    let issue_req = mock.received_requests().await.pop().unwrap();
+    drop(mock);
+
+    let link = app.get_unsubscribe_link(&issue_req);
+
+    // following the link only asks for confirmation
+    let resp = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp.text().await.unwrap().contains(r#"method="post""#));
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "confirmed");
+
+    let resp = app.api_client.post(link.clone()).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+
+    // idempotent
+    let resp = app.api_client.post(link.clone()).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.publish_issue("foo", "bar").await;
+    app.send_all_emails().await;
+}
+
+/// Mail clients that honour `List-Unsubscribe-Post` (RFC 8058) `POST` to the
+/// `List-Unsubscribe` url directly, without cookies or user interaction
+#[tokio::test]
+async fn one_click_unsubscribe() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.publish_issue("foo", "bar").await;
+    app.send_all_emails().await;
+    let issue_req = mock.received_requests().await.pop().unwrap();
+
+    let body: serde_json::Value = serde_json::from_slice(&issue_req.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|h| h["Name"] == name)
+            .unwrap()
+            .get("Value")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_owned()
+    };
+    assert_eq!(
+        header("List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+    let link = header("List-Unsubscribe");
+    let link = link.trim_start_matches('<').trim_end_matches('>');
+    assert_eq!(
+        link,
+        app.get_unsubscribe_link(&issue_req)
+            .as_str()
+            .replace(&format!(":{}", app.port), "")
+    );
+
+    // fresh client, i.e. no session
+    let resp = reqwest::Client::new()
+        .post(app.get_unsubscribe_link(&issue_req))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+}
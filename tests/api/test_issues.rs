@@ -0,0 +1,211 @@
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::check_redirect;
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn test_issue_requires_login() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "test_email": "admin@example.com",
+    });
+    let resp = app.post_newsletters_test(&body).await;
+    check_redirect(&resp, "/login");
+}
+
+/// Only the chosen address receives the issue, and the form can still be
+/// published with the same idempotency key
+#[tokio::test]
+async fn test_issue_is_sent_to_chosen_address_only() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let key = Uuid::new_v4().to_string();
+    let body = serde_json::json!({
+        "title": "foo",
+        "content": "Hi **{{ name }}**",
+        "idempotency_key": key,
+        "send_at": "",
+        "test_email": "admin@example.com",
+    });
+    let resp = app.post_newsletters_test(&body).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Test issue has been sent to admin@example.com."));
+
+    let req = mock.received_requests().await.pop().unwrap();
+    let sent: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+    assert_eq!(sent["To"], "admin@example.com");
+    assert_eq!(sent["Subject"], "foo");
+    // rendered like a delivered issue
+    assert!(sent["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("<strong>Jane Doe</strong>"));
+    assert!(sent["HtmlBody"].as_str().unwrap().contains("Unsubscribe"));
+    assert!(sent["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|h| h["Name"] == "List-Unsubscribe"));
+    drop(mock);
+
+    let issues = sqlx::query!("SELECT count(*) AS n FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.n, Some(0));
+    assert_eq!(app.queue_len().await, 0);
+
+    let resp = app.post_newsletters(&body).await;
+    check_redirect(&resp, "/admin/newsletters");
+    assert!(app
+        .get_newsletters_html()
+        .await
+        .contains("New issue is being published..."));
+    assert_eq!(app.queue_len().await, 1);
+}
+
+/// A subscriber's own address gets their actual placeholder values
+#[tokio::test]
+async fn test_issue_to_subscriber_uses_their_details() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    create_confirmed_subscriber(&app).await;
+    let sub = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let body = serde_json::json!({
+        "title": "foo",
+        "content": "Hi {{ name }}",
+        "test_email": sub.email,
+    });
+    app.post_newsletters_test(&body).await;
+
+    let req = mock.received_requests().await.pop().unwrap();
+    let sent: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+    assert!(sent["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("Hi {}", sub.name)));
+    // working link
+    let link = app.get_unsubscribe_link(&req);
+    let resp = reqwest::get(link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invalid_test_issue_is_not_sent() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for (content, test_email, msg) in [
+        ("bar", "not an email", "Invalid email"),
+        ("Hi {{ name", "admin@example.com", "Invalid template"),
+    ] {
+        let body = serde_json::json!({
+            "title": "foo",
+            "content": content,
+            "test_email": test_email,
+        });
+        let resp = app.post_newsletters_test(&body).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(resp.text().await.unwrap().contains(msg), "{msg}");
+    }
+}
+
+#[tokio::test]
+async fn provider_error_is_reported() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = serde_json::json!({
+        "title": "foo",
+        "content": "bar",
+        "test_email": "admin@example.com",
+    });
+    let resp = app.post_newsletters_test(&body).await;
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Failed to send test issue"));
+    assert_eq!(app.queue_len().await, 0);
+}
+
+#[tokio::test]
+async fn draft_can_be_sent_as_test() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let body = serde_json::json!({"title": "My draft", "content": "bar"});
+    app.post_draft("", &body).await;
+    let id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = serde_json::json!({"test_email": "admin@example.com"});
+    let resp = app.post_draft(&format!("/{id}/test"), &body).await;
+    check_redirect(&resp, &format!("/admin/newsletters/drafts/{id}"));
+    let html = app.get_draft(&format!("/{id}")).await.text().await.unwrap();
+    assert!(html.contains("Test issue has been sent to admin@example.com."));
+
+    let status = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "draft");
+    assert_eq!(app.queue_len().await, 0);
+}
@@ -0,0 +1,136 @@
+use wiremock::matchers::any;
+use wiremock::Mock;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::check_redirect;
+use crate::helpers::create_confirmed_subscriber;
+use crate::helpers::create_unconfirmed_subscriber;
+use crate::helpers::spawn_app;
+use crate::helpers::TestApp;
+
+async fn enable_welcome_email(
+    app: &TestApp,
+    title: &str,
+    content: &str,
+) {
+    let body = serde_json::json!({
+        "title": title,
+        "content": content,
+        "enabled": "on",
+    });
+    let resp = app.post_welcome_email(&body).await;
+    check_redirect(&resp, "/admin/welcome");
+}
+
+#[tokio::test]
+async fn welcome_email_requires_login() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .api_client
+        .get(format!("{}/admin/welcome", app.addr))
+        .send()
+        .await
+        .unwrap();
+    check_redirect(&resp, "/login");
+
+    let body = serde_json::json!({ "title": "foo", "content": "bar", "enabled": "on" });
+    let resp = app.post_welcome_email(&body).await;
+    check_redirect(&resp, "/login");
+}
+
+#[tokio::test]
+async fn welcome_email_is_disabled_by_default() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    assert_eq!(app.outbox_len().await, 0);
+}
+
+/// Confirming only enqueues the email; it is sent by the delivery worker, with
+/// the subject and content saved by the admin
+#[tokio::test]
+async fn confirming_enqueues_welcome_email() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+    enable_welcome_email(&app, "Welcome aboard", "Hi **{{ name }}**").await;
+
+    let html = app.get_welcome_email_html().await;
+    assert!(html.contains("Welcome email has been saved."));
+    assert!(html.contains(r#"value="Welcome aboard""#));
+    assert!(html.contains("checked"));
+
+    let link = create_unconfirmed_subscriber(&app).await;
+    // no mock is mounted, so sending inline would fail
+    let resp = reqwest::get(link.html.clone()).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let queued = sqlx::query!("SELECT subject FROM email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.subject, "Welcome aboard");
+
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.send_all_emails().await;
+
+    let name = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .name;
+    let req = mock.received_requests().await.pop().unwrap();
+    let sent: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+    assert_eq!(sent["Subject"], "Welcome aboard");
+    let html_body = sent["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains(&format!("<strong>{name}</strong>")));
+    // like an issue
+    assert!(html_body.contains("/subscriptions/unsubscribe?subscriber_id="));
+    drop(mock);
+
+    // the welcome email is only ever sent once, even if the subscriber
+    // confirms again with a new token
+    sqlx::query!("UPDATE subscriptions SET status = 'pending_confirmation'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET consumed_at = NULL")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    reqwest::get(link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(app.outbox_len().await, 0);
+}
+
+#[tokio::test]
+async fn invalid_welcome_template_is_rejected() {
+    let app = spawn_app().await;
+    app.login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    let body = serde_json::json!({
+        "title": "Welcome",
+        "content": "Hi {{ nickname }}",
+        "enabled": "on",
+    });
+    let resp = app.post_welcome_email(&body).await;
+    check_redirect(&resp, "/admin/welcome");
+
+    let html = app.get_welcome_email_html().await;
+    assert!(html.contains("Invalid template"));
+    // unchanged, and still disabled
+    let welcome = sqlx::query!("SELECT subject FROM welcome_email")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(welcome.subject, "Welcome!");
+    create_confirmed_subscriber(&app).await;
+    assert_eq!(app.outbox_len().await, 0);
+}